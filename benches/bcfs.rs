@@ -0,0 +1,45 @@
+//! Times `decompress_bcfs` on a synthetic archive.
+//! Compare `cargo bench` with `cargo bench --features parallel`.
+
+extern crate guitar_tabs;
+
+use std::time::Instant;
+
+use guitar_tabs::gpx;
+use guitar_tabs::gpx::SECTOR_SIZE;
+
+const FILE_COUNT: usize = 64;
+const BLOCKS_PER_FILE: usize = 256;
+const ITERATIONS: u32 = 20;
+
+/// BCFS body (without magic) from `write_bcfs`: an empty header sector,
+/// then per file an index sector and its data sectors.
+fn synthetic_archive() -> Vec<u8> {
+    let files: Vec<_> = (0..FILE_COUNT)
+        .map(|file| {
+            let data = (0..BLOCKS_PER_FILE * SECTOR_SIZE)
+                .map(|idx| (file + idx / SECTOR_SIZE) as u8)
+                .collect();
+            gpx::File::new(format!("file{}", file), data)
+        })
+        .collect();
+    gpx::write_bcfs(&files)[4..].to_vec()
+}
+
+fn main() {
+    let data = synthetic_archive();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let files = gpx::decompress_bcfs(&data).unwrap();
+        assert_eq!(files.len(), FILE_COUNT);
+    }
+    let elapsed = start.elapsed();
+    let per_iteration = elapsed / ITERATIONS;
+    println!(
+        "decompress_bcfs: {} files, {} MB: {}.{:03} ms/iter",
+        FILE_COUNT,
+        data.len() / (1024 * 1024),
+        per_iteration.as_secs() * 1000 + u64::from(per_iteration.subsec_nanos() / 1_000_000),
+        (per_iteration.subsec_nanos() / 1000) % 1000
+    );
+}
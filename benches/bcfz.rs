@@ -0,0 +1,81 @@
+//! Times `decompress_bcfz` on a stream made almost entirely of uncompressed chunks,
+//! and `BitBuffer::read` on a multi-MB payload, byte aligned and one bit off,
+//! against the bit-at-a-time baseline of a `read_bits(8)` loop.
+
+extern crate guitar_tabs;
+
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+use guitar_tabs::bitbuffer::BitBuffer;
+use guitar_tabs::gpx;
+
+const PAYLOAD_LEN: usize = 4 * 1024 * 1024;
+const READ_LEN: usize = 64 * 1024;
+const ITERATIONS: u32 = 10;
+
+fn report(name: &str, per_iteration: Duration) {
+    println!(
+        "{}: {} MB: {}.{:03} ms/iter",
+        name,
+        PAYLOAD_LEN / (1024 * 1024),
+        per_iteration.as_secs() * 1000 + u64::from(per_iteration.subsec_nanos() / 1_000_000),
+        (per_iteration.subsec_nanos() / 1000) % 1000
+    );
+}
+
+/// Reads the payload through `BitBuffer::read` in `READ_LEN` pieces, after `skip` bits.
+fn time_reads(payload: &[u8], skip: u64) -> Duration {
+    let mut buf = vec![0u8; READ_LEN];
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let mut bb = BitBuffer::new(payload);
+        bb.skip_bits(skip).unwrap();
+        let mut total = 0;
+        loop {
+            let read = bb.read(&mut buf).unwrap();
+            if read == 0 {
+                break;
+            }
+            total += read;
+        }
+        assert_eq!(total, PAYLOAD_LEN - if skip > 0 { 1 } else { 0 });
+    }
+    start.elapsed() / ITERATIONS
+}
+
+/// The baseline `Read` replaced: every byte assembled from `read_bits(8)`.
+fn time_read_bits(payload: &[u8]) -> Duration {
+    let mut buf = vec![0u8; PAYLOAD_LEN];
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let mut bb = BitBuffer::new(payload);
+        for byte in buf.iter_mut() {
+            *byte = bb.read_bits(8).unwrap() as u8;
+        }
+        assert_eq!(buf[..], payload[..]);
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    let mut seed = 12345u32;
+    let payload: Vec<u8> = (0..PAYLOAD_LEN)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        })
+        .collect();
+    let compressed = gpx::compress_bcfz(&payload);
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let decompressed = gpx::decompress_bcfz(&compressed).unwrap();
+        assert_eq!(decompressed.len(), PAYLOAD_LEN);
+    }
+    report("decompress_bcfz of literals", start.elapsed() / ITERATIONS);
+
+    report("read_bits(8) loop, baseline", time_read_bits(&payload));
+    report("BitBuffer::read, byte aligned", time_reads(&payload, 0));
+    report("BitBuffer::read, unaligned", time_reads(&payload, 1));
+}
@@ -0,0 +1,17 @@
+//! `gpx::read` on arbitrary input, then the score inside if there is one.
+//! Errors are fine, panics and hangs are not.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate guitar_tabs;
+
+use guitar_tabs::gpx::{self, Archive};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(files) = gpx::read(data.to_vec()) {
+        if let Some(score) = files.score() {
+            let _ = gpx::score::parse(score.data());
+        }
+    }
+});
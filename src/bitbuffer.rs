@@ -1,21 +1,143 @@
+//! Bit level reading and writing in the layout BCFZ uses: bits are taken from each byte
+//! MSB first, `read_bits` assembles them MSB first and `read_bits_reversed` LSB first.
+//!
+//! ```
+//! use guitar_tabs::bitbuffer::{BitBuffer, BitWriter};
+//!
+//! let mut writer = BitWriter::new();
+//! writer.write_bit(1);
+//! writer.write_bits(0b0110, 4);
+//! writer.write_bits_reversed(0b0110, 4);
+//! let data = writer.into_bytes();
+//! assert_eq!(data, vec![0b1011_0011, 0b0000_0000]);
+//!
+//! let mut reader = BitBuffer::new(&data);
+//! assert_eq!(reader.read_bit().unwrap(), 1);
+//! assert_eq!(reader.read_bits(4).unwrap(), 0b0110);
+//! assert_eq!(reader.read_bits_reversed(4).unwrap(), 0b0110);
+//! assert_eq!(reader.bit_offset(), 9);
+//! ```
+
+use std::cmp;
+use std::error;
+use std::fmt;
 use std::io::{self, Cursor, Read};
+use std::mem;
+
+/// Widest word `read_bits` and `read_bits_reversed` can return.
+const WORD_BITS: usize = mem::size_of::<usize>() * 8;
+
+/// Error of a bit level read, with the position it happened at.
+/// Converts into an `io::Error` of the same kind, keeping that context in the message.
+#[derive(Debug)]
+pub struct BitError {
+    // Boxed to keep the `Result`s of the per bit reads small.
+    context: Box<BitErrorContext>,
+}
+
+#[derive(Debug)]
+struct BitErrorContext {
+    bit_offset: u64,
+    requested_bits: u64,
+    completed_bits: u64,
+    error: io::Error,
+}
+
+impl BitError {
+    #[cold]
+    fn new(bit_offset: u64, requested_bits: u64, kind: io::ErrorKind, message: String) -> BitError {
+        BitError {
+            context: Box::new(BitErrorContext {
+                bit_offset: bit_offset,
+                requested_bits: requested_bits,
+                completed_bits: 0,
+                error: io::Error::new(kind, message),
+            }),
+        }
+    }
+
+    /// Bits consumed before the failing read, see `BitBuffer::bit_offset`.
+    pub fn bit_offset(&self) -> u64 {
+        self.context.bit_offset
+    }
+
+    /// How many bits the failing read asked for.
+    pub fn requested_bits(&self) -> u64 {
+        self.context.requested_bits
+    }
 
+    /// Bits consumed anyway: always 0 for reads, the bits skipped before the end for `skip_bits`.
+    pub fn completed_bits(&self) -> u64 {
+        self.context.completed_bits
+    }
+
+    pub fn kind(&self) -> io::ErrorKind {
+        self.context.error.kind()
+    }
+}
+
+impl fmt::Display for BitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} bits requested at bit {}",
+            self.context.error, self.context.requested_bits, self.context.bit_offset
+        )
+    }
+}
+
+impl error::Error for BitError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.context.error)
+    }
+}
+
+impl From<BitError> for io::Error {
+    fn from(error: BitError) -> io::Error {
+        io::Error::new(error.kind(), error)
+    }
+}
+
+/// Reads bits out of a byte slice. Implements `Read` for whole bytes starting at any bit,
+/// so `byteorder` can be used on it too.
 pub struct BitBuffer<'a> {
     bit_position: u8,
     byte: u8,
     cursor: Cursor<&'a [u8]>,
 }
 
+/// Saved read position of a `BitBuffer`, see `BitBuffer::checkpoint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    bit_position: u8,
+    byte: u8,
+    position: u64,
+}
+
+/// Whole bytes starting at the current bit. At the end of the data reads come back short,
+/// with `Ok(0)` once not even one full byte is left; trailing bits stay readable bit by bit.
 impl<'a> Read for BitBuffer<'a> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        for x in 0..buf.len() {
-            buf[x] = try!(self.read_bits(8)) as u8;
+        if self.bit_position == 8 {
+            // Byte aligned: copy straight from the underlying data.
+            return self.cursor.read(buf);
+        }
+        // The rest of the current byte followed by the head of the next one.
+        let shift = self.bit_position;
+        let position = self.cursor.position() as usize;
+        let rest = self.cursor.get_ref().get(position..).unwrap_or(&[]);
+        let read = cmp::min(buf.len(), rest.len());
+        for (out, &next) in buf[..read].iter_mut().zip(rest) {
+            *out = (self.byte << shift) | (next >> (8 - shift));
+            self.byte = next;
         }
-        Ok(buf.len())
+        self.cursor.set_position((position + read) as u64);
+        Ok(read)
     }
 }
 
 impl<'a> BitBuffer<'a> {
+    /// Starts at the first (most significant) bit of `data`.
     pub fn new(data: &[u8]) -> BitBuffer {
         BitBuffer {
             bit_position: 8,
@@ -24,44 +146,294 @@ impl<'a> BitBuffer<'a> {
         }
     }
 
-    // Reads bit one by one
+    /// Number of bits consumed so far.
+    pub fn bit_offset(&self) -> u64 {
+        let fetched_bits = self.cursor.position() * 8;
+        fetched_bits.saturating_sub(8 - self.bit_position as u64)
+    }
+
+    /// Offset of the byte the next bit comes from.
+    pub fn byte_offset(&self) -> u64 {
+        self.bit_offset() / 8
+    }
+
+    /// Skips the unread bits of the current byte, a no-op when already on a byte boundary.
+    pub fn align_to_byte(&mut self) {
+        self.bit_position = 8;
+    }
+
+    /// Skips `count` bits without reading them one by one.
+    /// Skipping past the end stops at the end, the error's `completed_bits` is how many were skipped.
+    pub fn skip_bits(&mut self, count: u64) -> Result<(), BitError> {
+        let start = self.bit_offset();
+        let skipped = cmp::min(count, self.remaining_bits());
+        let target = start + skipped;
+        let data = *self.cursor.get_ref();
+        let (index, bit) = ((target / 8) as usize, (target % 8) as u8);
+        if bit == 0 {
+            self.cursor.set_position(index as u64);
+            self.bit_position = 8;
+        } else {
+            self.byte = data[index];
+            self.cursor.set_position(index as u64 + 1);
+            self.bit_position = bit;
+        }
+        if skipped < count {
+            let mut error = BitError::new(
+                start,
+                count,
+                io::ErrorKind::UnexpectedEof,
+                format!("Skipped only {} bits before the end of the data", skipped),
+            );
+            error.context.completed_bits = skipped;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Whole bytes left to read from the current bit on.
+    /// Always known for the slice backed buffer, `None` is left for sources of unknown length.
+    pub fn remaining_bytes(&self) -> Option<u64> {
+        Some(self.remaining_bits() / 8)
+    }
+
+    /// Reads a single bit, 0 or 1.
+    #[inline]
+    pub fn read_bit(&mut self) -> Result<u8, BitError> {
+        try!(self.check_available(1, 1));
+        Ok(self.next_bit())
+    }
+
+    /// Next bit, the caller has checked there is one.
     #[inline]
-    pub fn read_bit(&mut self) -> io::Result<u8> {
+    fn next_bit(&mut self) -> u8 {
         if self.bit_position == 8 {
-            let buf = &mut [0u8];
-            try!(self.cursor.read(buf));
-            self.byte = buf[0];
+            let position = self.cursor.position();
+            self.byte = self.cursor.get_ref()[position as usize];
+            self.cursor.set_position(position + 1);
             self.bit_position = 0;
         }
         let bit = (self.byte >> (7 - self.bit_position) as usize) & 0x1; //MSB
         self.bit_position += 1;
-        Ok(bit)
+        bit
+    }
+
+    /// Saves the current read position, cached partial byte included.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            bit_position: self.bit_position,
+            byte: self.byte,
+            position: self.cursor.position(),
+        }
+    }
+
+    /// Goes back to a position saved by `checkpoint`.
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        self.bit_position = checkpoint.bit_position;
+        self.byte = checkpoint.byte;
+        self.cursor.set_position(checkpoint.position);
+    }
+
+    /// Returns the next bit without consuming it.
+    pub fn peek_bit(&mut self) -> Result<u8, BitError> {
+        self.peek_bits(1).map(|bit| bit as u8)
+    }
+
+    /// Returns what `read_bits(count)` would, leaving the position untouched,
+    /// errors included.
+    pub fn peek_bits(&mut self, count: usize) -> Result<u64, BitError> {
+        let checkpoint = self.checkpoint();
+        let result = self.read_bits(count);
+        self.restore(checkpoint);
+        result.map(|word| word as u64)
+    }
+
+    fn check_bit_count(&self, count: usize, word_bits: usize) -> Result<(), BitError> {
+        if count > word_bits {
+            return Err(BitError::new(
+                self.bit_offset(),
+                count as u64,
+                io::ErrorKind::InvalidData,
+                format!("Can't read into a {} bit word", word_bits),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reads `count` bits, the first one read becomes the most significant.
+    /// A count of 0 returns Ok(0) without consuming anything,
+    /// more bits than fit in a usize is an InvalidData error and consumes nothing either,
+    /// running out of data is an UnexpectedEof error, again consuming nothing.
+    ///
+    /// ```
+    /// use guitar_tabs::bitbuffer::BitBuffer;
+    ///
+    /// let mut bb = BitBuffer::new(&[0b1100_1010]);
+    /// assert_eq!(bb.read_bits(3).unwrap(), 0b110);
+    /// assert_eq!(bb.read_bits(5).unwrap(), 0b01010);
+    /// assert!(bb.read_bits(1).is_err());
+    /// ```
+    pub fn read_bits(&mut self, count: usize) -> Result<usize, BitError> {
+        self.read_bits_sized(count, WORD_BITS)
+            .map(|word| word as usize)
     }
 
-    // bigEndian MSB
-    pub fn read_bits(&mut self, count: usize) -> io::Result<usize> {
-        let mut word = 0usize;
-        assert!(count <= 64);
+    /// `read_bits` into a `u64`, up to 64 bits on any platform.
+    pub fn read_bits_u64(&mut self, count: usize) -> Result<u64, BitError> {
+        self.read_bits_sized(count, 64)
+    }
+
+    /// `read_bits` into a `u32`, more than 32 bits is an InvalidData error.
+    pub fn read_bits_u32(&mut self, count: usize) -> Result<u32, BitError> {
+        self.read_bits_sized(count, 32).map(|word| word as u32)
+    }
+
+    /// Reads a `count` bit two's complement number, sign extended to an `i32`.
+    pub fn read_bits_i32(&mut self, count: usize) -> Result<i32, BitError> {
+        let word = try!(self.read_bits_sized(count, 32));
+        if count == 0 {
+            return Ok(0);
+        }
+        let shift = 64 - count;
+        Ok((((word << shift) as i64) >> shift) as i32)
+    }
+
+    fn read_bits_sized(&mut self, count: usize, word_bits: usize) -> Result<u64, BitError> {
+        try!(self.check_available(count, word_bits));
+        let mut word = 0u64;
         for idx in 0..count {
-            let bit = try!(self.read_bit());
-            word = word | ((bit as usize) << (count - 1 - idx));
+            word = word | ((self.next_bit() as u64) << (count - 1 - idx));
         }
         Ok(word)
     }
 
-    pub fn read_bits_reversed(&mut self, count: usize) -> io::Result<usize> {
-        let mut word = 0usize;
+    /// Reads `count` bits, the first one read becomes the least significant.
+    /// Same count rules as `read_bits`.
+    ///
+    /// ```
+    /// use guitar_tabs::bitbuffer::BitBuffer;
+    ///
+    /// let mut bb = BitBuffer::new(&[0b1100_1010]);
+    /// assert_eq!(bb.read_bits_reversed(3).unwrap(), 0b011);
+    /// ```
+    pub fn read_bits_reversed(&mut self, count: usize) -> Result<usize, BitError> {
+        self.read_bits_reversed_sized(count, WORD_BITS)
+            .map(|word| word as usize)
+    }
+
+    /// `read_bits_reversed` into a `u32`, more than 32 bits is an InvalidData error.
+    pub fn read_bits_reversed_u32(&mut self, count: usize) -> Result<u32, BitError> {
+        self.read_bits_reversed_sized(count, 32)
+            .map(|word| word as u32)
+    }
+
+    fn read_bits_reversed_sized(
+        &mut self,
+        count: usize,
+        word_bits: usize,
+    ) -> Result<u64, BitError> {
+        try!(self.check_available(count, word_bits));
+        let mut word = 0u64;
         for idx in 0..count {
-            let bit = try!(self.read_bit());
-            word = word | ((bit as usize) << idx);
+            word = word | ((self.next_bit() as u64) << idx);
         }
         Ok(word)
     }
+
+    /// Checks a read of `count` bits up front, so a failing one consumes nothing.
+    #[inline]
+    fn check_available(&self, count: usize, word_bits: usize) -> Result<(), BitError> {
+        try!(self.check_bit_count(count, word_bits));
+        if count as u64 > self.remaining_bits() {
+            return Err(self.eof_error(count as u64));
+        }
+        Ok(())
+    }
+
+    fn remaining_bits(&self) -> u64 {
+        let data_len = self.cursor.get_ref().len() as u64;
+        (data_len - self.cursor.position()) * 8 + (8 - self.bit_position as u64)
+    }
+
+    #[cold]
+    fn eof_error(&self, requested_bits: u64) -> BitError {
+        BitError::new(
+            self.bit_offset(),
+            requested_bits,
+            io::ErrorKind::UnexpectedEof,
+            format!("Only {} bits left", self.remaining_bits()),
+        )
+    }
+}
+
+/// Writing counterpart of `BitBuffer`, packs bits MSB first.
+pub struct BitWriter {
+    bit_position: u8,
+    byte: u8,
+    data: Vec<u8>,
+}
+
+impl Default for BitWriter {
+    fn default() -> BitWriter {
+        BitWriter::new()
+    }
+}
+
+impl BitWriter {
+    pub fn new() -> BitWriter {
+        BitWriter {
+            bit_position: 0,
+            byte: 0,
+            data: vec![],
+        }
+    }
+
+    /// Writes the lowest bit of `bit`.
+    #[inline]
+    pub fn write_bit(&mut self, bit: u8) {
+        self.byte |= (bit & 0x1) << (7 - self.bit_position); //MSB
+        self.bit_position += 1;
+        if self.bit_position == 8 {
+            self.data.push(self.byte);
+            self.byte = 0;
+            self.bit_position = 0;
+        }
+    }
+
+    /// Writes the low `count` bits of `word`, most significant first, mirrors `read_bits`.
+    pub fn write_bits(&mut self, word: usize, count: usize) {
+        for idx in 0..count {
+            self.write_bit((word >> (count - 1 - idx)) as u8);
+        }
+    }
+
+    /// Writes the low `count` bits of `word`, least significant first,
+    /// mirrors `read_bits_reversed`.
+    pub fn write_bits_reversed(&mut self, word: usize, count: usize) {
+        for idx in 0..count {
+            self.write_bit((word >> idx) as u8);
+        }
+    }
+
+    /// Writes whole bytes starting at the current bit.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_bits(byte as usize, 8);
+        }
+    }
+
+    /// Returns the written bytes, a partial last byte is padded with zero bits.
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        if self.bit_position > 0 {
+            self.data.push(self.byte);
+        }
+        self.data
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use bitbuffer::BitBuffer;
+    use bitbuffer::{BitBuffer, BitWriter};
     use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
 
     #[test]
@@ -116,4 +488,456 @@ mod tests {
         let num = bb.read_u16::<BigEndian>().unwrap(); //00101011_11000011
         assert_eq!(num, 11203);
     }
+    #[test]
+    pub fn test_bit_writer_mirrors_bit_buffer() {
+        let mut bw = BitWriter::new();
+        bw.write_bit(1);
+        bw.write_bits(5, 4);
+        bw.write_bits_reversed(83, 7);
+        bw.write_bytes(&[0xab]);
+        let data = bw.into_bytes();
+        assert_eq!(data.len(), 3);
+
+        let mut bb = BitBuffer::new(&data);
+        assert_eq!(bb.read_bit().unwrap(), 1);
+        assert_eq!(bb.read_bits(4).unwrap(), 5);
+        assert_eq!(bb.read_bits_reversed(7).unwrap(), 83);
+        assert_eq!(bb.read_bits(8).unwrap(), 0xab);
+        assert_eq!(bb.read_bits(4).unwrap(), 0);
+    }
+    #[test]
+    pub fn test_bit_buffer_offsets() {
+        let data: &[u8] = &[0b11001010, 0b11110000, 0b00001111];
+        let mut bb = BitBuffer::new(data);
+        assert_eq!((bb.bit_offset(), bb.byte_offset()), (0, 0));
+        bb.read_bits(3).unwrap();
+        assert_eq!((bb.bit_offset(), bb.byte_offset()), (3, 0));
+        bb.read_bits(7).unwrap();
+        assert_eq!((bb.bit_offset(), bb.byte_offset()), (10, 1));
+        bb.read_bits(6).unwrap();
+        assert_eq!((bb.bit_offset(), bb.byte_offset()), (16, 2));
+    }
+    #[test]
+    pub fn test_bit_buffer_align_to_byte() {
+        let data: &[u8] = &[0b11001010, 0b11110000, 0b00001111];
+        let mut bb = BitBuffer::new(data);
+        bb.align_to_byte();
+        assert_eq!(bb.bit_offset(), 0);
+        bb.read_bits(3).unwrap();
+        bb.align_to_byte();
+        assert_eq!(bb.bit_offset(), 8);
+        assert_eq!(bb.read_bits(8).unwrap(), 0b11110000);
+        bb.align_to_byte();
+        assert_eq!(bb.bit_offset(), 16);
+        assert_eq!(bb.read_bits(4).unwrap(), 0);
+    }
+    #[test]
+    pub fn test_bit_buffer_read_bits_counts() {
+        use std::io::ErrorKind;
+        let data: &[u8] = &[0xff, 0, 0, 0, 0, 0, 0, 0x01, 0b10100000];
+        let mut bb = BitBuffer::new(data);
+        assert_eq!(bb.read_bits(0).unwrap(), 0);
+        assert_eq!(bb.read_bits_reversed(0).unwrap(), 0);
+        assert_eq!(bb.bit_offset(), 0);
+        for &count in &[65, 1000, usize::max_value()] {
+            assert_eq!(
+                bb.read_bits(count).unwrap_err().kind(),
+                ErrorKind::InvalidData
+            );
+            assert_eq!(
+                bb.read_bits_reversed(count).unwrap_err().kind(),
+                ErrorKind::InvalidData
+            );
+        }
+        assert_eq!(bb.bit_offset(), 0);
+        assert_eq!(bb.read_bits(64).unwrap(), 0xff00_0000_0000_0001);
+        assert_eq!(bb.read_bits(1).unwrap(), 0b1);
+        // Zero counts leave a partially read byte alone as well.
+        assert_eq!(bb.read_bits(0).unwrap(), 0);
+        assert_eq!(bb.read_bits_reversed(0).unwrap(), 0);
+        assert_eq!(bb.read_bits_u32(0).unwrap(), 0);
+        assert_eq!(bb.read_bits_i32(0).unwrap(), 0);
+        assert_eq!(bb.bit_offset(), 65);
+        assert_eq!(bb.read_bits(2).unwrap(), 0b01);
+    }
+    #[test]
+    pub fn test_bit_buffer_peek_bits() {
+        let data: &[u8] = &[0b11001010, 0b11110000, 0b00001111];
+        let mut peeking = BitBuffer::new(data);
+        let mut reading = BitBuffer::new(data);
+        for &count in &[3, 7, 1, 8, 1] {
+            let peeked = peeking.peek_bits(count).unwrap();
+            assert_eq!(peeking.peek_bits(count).unwrap(), peeked);
+            assert_eq!(peeking.read_bits(count).unwrap() as u64, peeked);
+            assert_eq!(reading.read_bits(count).unwrap() as u64, peeked);
+            assert_eq!(peeking.bit_offset(), reading.bit_offset());
+        }
+        assert_eq!(peeking.peek_bit().unwrap(), 1);
+        assert_eq!(peeking.read_bit().unwrap(), 1);
+        assert!(peeking.peek_bits(65).is_err());
+        assert_eq!(peeking.bit_offset(), 21);
+        // Across the end of the data peeking behaves like reading.
+        let peeked = peeking.peek_bits(8).map_err(|e| e.kind());
+        let read = peeking.read_bits(8).map(|word| word as u64);
+        assert_eq!(read.map_err(|e| e.kind()), peeked);
+    }
+
+    #[test]
+    pub fn test_bit_buffer_skip_bits() {
+        let data: Vec<u8> = (0..64).collect();
+        let mut bb = BitBuffer::new(&data);
+        assert_eq!(bb.remaining_bytes(), Some(64));
+        bb.skip_bits(0).unwrap();
+        assert_eq!(bb.bit_offset(), 0);
+        bb.skip_bits(3).unwrap();
+        assert_eq!(bb.remaining_bytes(), Some(63));
+        // Across many byte boundaries, landing mid byte.
+        bb.skip_bits(8 * 20 + 2).unwrap();
+        assert_eq!(bb.bit_offset(), 165);
+        assert_eq!(bb.read_bits(3).unwrap(), 20 & 0b111);
+        assert_eq!(bb.read_bits(8).unwrap(), 21);
+        // Landing on a byte boundary.
+        bb.skip_bits(8 * 10).unwrap();
+        assert_eq!(bb.read_bits(8).unwrap(), 32);
+        assert_eq!(bb.remaining_bytes(), Some(31));
+
+        let err = bb.skip_bits(8 * 31 + 5).unwrap_err();
+        assert_eq!(err.kind(), ::std::io::ErrorKind::UnexpectedEof);
+        assert_eq!(
+            (err.bit_offset(), err.requested_bits(), err.completed_bits()),
+            (264, 253, 248)
+        );
+        assert_eq!(bb.bit_offset(), 64 * 8);
+        assert_eq!(bb.remaining_bytes(), Some(0));
+        assert!(bb.read_bit().is_err());
+        assert!(bb.skip_bits(1).is_err());
+        bb.skip_bits(0).unwrap();
+    }
+
+    #[test]
+    pub fn test_bit_buffer_eof() {
+        use std::io::{ErrorKind, Read};
+        let data: &[u8] = &[0xab, 0xcd];
+        let mut bb = BitBuffer::new(data);
+        assert_eq!(bb.read_bits(12).unwrap(), 0xabc);
+        assert_eq!(
+            bb.read_bits(5).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+        assert_eq!(
+            bb.read_bits_reversed(5).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+        let err = bb.read_bits_i32(5).unwrap_err();
+        assert_eq!((err.bit_offset(), err.requested_bits()), (12, 5));
+        assert_eq!(
+            err.to_string(),
+            "Only 4 bits left: 5 bits requested at bit 12"
+        );
+        let err: ::std::io::Error = err.into();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        assert!(err.to_string().contains("at bit 12"));
+        assert_eq!(bb.bit_offset(), 12);
+        // Four bits left: not a whole byte for `read`, but still there for read_bits.
+        assert_eq!(bb.read(&mut [0u8; 1]).unwrap(), 0);
+        assert_eq!(bb.read_bits(4).unwrap(), 0xd);
+        assert_eq!(bb.read_bit().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(bb.bit_offset(), 16);
+        assert_eq!(bb.read(&mut [0u8; 1]).unwrap(), 0);
+
+        // Short reads right at the boundary, aligned and not.
+        let mut bb = BitBuffer::new(data);
+        let mut buf = [0u8; 3];
+        assert_eq!(bb.read(&mut buf).unwrap(), 2);
+        assert_eq!(buf, [0xab, 0xcd, 0]);
+        let mut bb = BitBuffer::new(data);
+        bb.read_bits(3).unwrap();
+        let mut buf = [0u8; 3];
+        assert_eq!(bb.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 0x5e);
+        assert_eq!(bb.read_bits(5).unwrap(), 0b01101);
+        let mut bb = BitBuffer::new(data);
+        let mut rest = vec![];
+        bb.read_bits(8).unwrap();
+        assert_eq!(bb.read_to_end(&mut rest).unwrap(), 1);
+        assert_eq!(rest, vec![0xcd]);
+        let mut bb = BitBuffer::new(data);
+        assert_eq!(
+            bb.read_exact(&mut [0u8; 3]).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    pub fn test_bit_buffer_read_matches_read_bits() {
+        use std::io::Read;
+        let data: &[u8] = &[
+            0b11001010, 0b11110000, 0b00001111, 0b10101010, 0x5a, 0xc3, 0x81,
+        ];
+        for shift in 0..8 {
+            let mut bulk = BitBuffer::new(data);
+            let mut bitwise = BitBuffer::new(data);
+            bulk.read_bits(shift).unwrap();
+            bitwise.read_bits(shift).unwrap();
+            let mut buf = [0u8; 5];
+            assert_eq!(bulk.read(&mut buf).unwrap(), 5);
+            let expected: Vec<u8> = (0..5)
+                .map(|_| bitwise.read_bits(8).unwrap() as u8)
+                .collect();
+            assert_eq!(buf.to_vec(), expected);
+            assert_eq!(bulk.read_bits(3).unwrap(), bitwise.read_bits(3).unwrap());
+        }
+    }
+
+    #[test]
+    pub fn test_bit_buffer_checkpoint_restore() {
+        let data: &[u8] = &[0b11001010, 0b11110000, 0b00001111];
+        let mut bb = BitBuffer::new(data);
+        let start = bb.checkpoint();
+        assert_eq!(bb.read_bits(5).unwrap(), 0b11001);
+        let middle = bb.checkpoint();
+        assert_eq!(bb.read_bits(6).unwrap(), 0b010111);
+        let crossed = bb.checkpoint();
+        assert_eq!(bb.read_bits(10).unwrap(), 0b1000000001);
+
+        bb.restore(middle);
+        assert_eq!(bb.bit_offset(), 5);
+        assert_eq!(bb.read_bits(6).unwrap(), 0b010111);
+        assert_eq!(bb.checkpoint(), crossed);
+        bb.restore(start);
+        assert_eq!(bb.read_bits(8).unwrap(), 0b11001010);
+        bb.restore(crossed);
+        assert_eq!(bb.bit_offset(), 11);
+        assert_eq!(bb.read_bits_reversed(5).unwrap(), 0b00001);
+        bb.restore(crossed);
+        assert_eq!(bb.read_bits(13).unwrap(), 0b1000000001111);
+    }
+
+    #[test]
+    pub fn test_bit_buffer_typed_reads() {
+        let data: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff, 0x80];
+        let mut bb = BitBuffer::new(data);
+        assert_eq!(bb.read_bits_i32(1).unwrap(), -1);
+        assert_eq!(bb.read_bits_i32(31).unwrap(), -1);
+        assert_eq!(bb.read_bits_i32(32).unwrap(), 0x7fffffff);
+        assert_eq!(bb.read_bits_i32(1).unwrap(), -1);
+        assert_eq!(bb.read_bits_i32(0).unwrap(), 0);
+
+        let mut bb = BitBuffer::new(&[0x00, 0x00, 0x00, 0x00, 0x80]);
+        assert_eq!(bb.read_bits_i32(1).unwrap(), 0);
+        assert_eq!(bb.read_bits_i32(31).unwrap(), 0);
+        assert_eq!(bb.read_bits_i32(8).unwrap(), -128);
+
+        let mut bb = BitBuffer::new(&[0x80, 0x00, 0x00, 0x01]);
+        assert_eq!(bb.read_bits_i32(32).unwrap(), i32::min_value() + 1);
+
+        let mut bb = BitBuffer::new(data);
+        assert_eq!(bb.read_bits_u32(4).unwrap(), 0xf);
+        assert!(bb.read_bits_u32(33).is_err());
+        assert!(bb.read_bits_i32(33).is_err());
+        assert_eq!(bb.bit_offset(), 4);
+        assert_eq!(bb.read_bits_u64(64).unwrap(), 0xfffffff7fffffff8);
+        assert!(bb.read_bits_u64(65).is_err());
+
+        let mut bb = BitBuffer::new(&[0b1100_1010, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(bb.read_bits_reversed_u32(3).unwrap(), 0b011);
+        assert!(bb.read_bits_reversed_u32(33).is_err());
+        assert_eq!(bb.bit_offset(), 3);
+        assert_eq!(bb.read_bits_reversed_u32(32).unwrap(), 0b1010);
+    }
+}
+
+/// Differential tests against a plain `Vec<bool>` model of the same bit conventions.
+#[cfg(test)]
+mod model_tests {
+    use super::{BitBuffer, BitError, WORD_BITS};
+    use proptest::prelude::*;
+    use std::io::{ErrorKind, Read};
+
+    /// Bit offset, requested bits, completed bits and kind of a failed read,
+    /// as reported by `BitError`.
+    type Failure = (u64, u64, u64, ErrorKind);
+
+    fn failure(error: BitError) -> Failure {
+        (
+            error.bit_offset(),
+            error.requested_bits(),
+            error.completed_bits(),
+            error.kind(),
+        )
+    }
+
+    /// Bits MSB first within each byte, reads that run past the end fail and consume nothing.
+    struct Model {
+        bits: Vec<bool>,
+        position: usize,
+    }
+
+    impl Model {
+        fn new(data: &[u8]) -> Model {
+            Model {
+                bits: data
+                    .iter()
+                    .flat_map(|byte| (0..8).rev().map(move |idx| (byte >> idx) & 1 == 1))
+                    .collect(),
+                position: 0,
+            }
+        }
+
+        fn remaining(&self) -> usize {
+            self.bits.len() - self.position
+        }
+
+        fn fail(&self, count: usize, kind: ErrorKind) -> Failure {
+            (self.position as u64, count as u64, 0, kind)
+        }
+
+        fn read_bit(&mut self) -> Result<u8, Failure> {
+            let bit = match self.bits.get(self.position) {
+                Some(&bit) => bit,
+                None => return Err(self.fail(1, ErrorKind::UnexpectedEof)),
+            };
+            self.position += 1;
+            Ok(bit as u8)
+        }
+
+        fn read_bits(
+            &mut self,
+            count: usize,
+            word_bits: usize,
+            reversed: bool,
+        ) -> Result<u64, Failure> {
+            if count > word_bits {
+                return Err(self.fail(count, ErrorKind::InvalidData));
+            }
+            if count > self.remaining() {
+                return Err(self.fail(count, ErrorKind::UnexpectedEof));
+            }
+            let bits: Vec<u8> = (0..count).map(|_| self.read_bit().unwrap()).collect();
+            let order: Vec<usize> = if reversed {
+                (0..count).collect()
+            } else {
+                (0..count).rev().collect()
+            };
+            Ok(bits
+                .iter()
+                .zip(order)
+                .fold(0, |word, (&bit, shift)| word | (bit as u64) << shift))
+        }
+
+        fn read_i32(&mut self, count: usize) -> Result<i32, Failure> {
+            let word = self.read_bits(count, 32, false)?;
+            if count > 0 && word >> (count - 1) == 1 {
+                Ok((word as i64 - (1i64 << count)) as i32)
+            } else {
+                Ok(word as i32)
+            }
+        }
+
+        fn align_to_byte(&mut self) {
+            self.position = (self.position + 7) / 8 * 8;
+        }
+
+        fn skip_bits(&mut self, count: usize) -> Result<(), Failure> {
+            let (offset, requested, _, kind) = self.fail(count, ErrorKind::UnexpectedEof);
+            let skipped = ::std::cmp::min(count, self.remaining());
+            self.position += skipped;
+            if skipped < count {
+                return Err((offset, requested, skipped as u64, kind));
+            }
+            Ok(())
+        }
+
+        fn read_bytes(&mut self, len: usize) -> Vec<u8> {
+            let len = ::std::cmp::min(len, self.remaining() / 8);
+            (0..len)
+                .map(|_| self.read_bits(8, WORD_BITS, false).unwrap() as u8)
+                .collect()
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Bit,
+        Bits(usize),
+        Reversed(usize),
+        ReversedU32(usize),
+        I32(usize),
+        Peek(usize),
+        Bytes(usize),
+        Skip(usize),
+        Align,
+    }
+
+    fn op() -> BoxedStrategy<Op> {
+        prop_oneof![
+            Just(Op::Bit),
+            (0..70usize).prop_map(Op::Bits),
+            (0..70usize).prop_map(Op::Reversed),
+            (0..34usize).prop_map(Op::ReversedU32),
+            (0..34usize).prop_map(Op::I32),
+            (0..70usize).prop_map(Op::Peek),
+            (0..6usize).prop_map(Op::Bytes),
+            (0..100usize).prop_map(Op::Skip),
+            Just(Op::Align),
+        ]
+        .boxed()
+    }
+
+    proptest! {
+        #[test]
+        fn bit_buffer_matches_model(
+            data in proptest::collection::vec(any::<u8>(), 0..24),
+            ops in proptest::collection::vec(op(), 0..48),
+        ) {
+            let mut bb = BitBuffer::new(&data);
+            let mut model = Model::new(&data);
+            for op in ops {
+                match op {
+                    Op::Bit => prop_assert_eq!(bb.read_bit().map_err(failure), model.read_bit()),
+                    Op::Bits(count) => prop_assert_eq!(
+                        bb.read_bits(count).map(|word| word as u64).map_err(failure),
+                        model.read_bits(count, WORD_BITS, false)
+                    ),
+                    Op::Reversed(count) => prop_assert_eq!(
+                        bb.read_bits_reversed(count).map(|word| word as u64).map_err(failure),
+                        model.read_bits(count, WORD_BITS, true)
+                    ),
+                    Op::ReversedU32(count) => prop_assert_eq!(
+                        bb.read_bits_reversed_u32(count).map(|word| word as u64).map_err(failure),
+                        model.read_bits(count, 32, true)
+                    ),
+                    Op::I32(count) => prop_assert_eq!(
+                        bb.read_bits_i32(count).map_err(failure),
+                        model.read_i32(count)
+                    ),
+                    Op::Peek(count) => {
+                        let position = model.position;
+                        prop_assert_eq!(
+                            bb.peek_bits(count).map_err(failure),
+                            model.read_bits(count, WORD_BITS, false)
+                        );
+                        model.position = position;
+                    }
+                    Op::Bytes(len) => {
+                        let mut buf = vec![0xaa; len];
+                        let read = bb.read(&mut buf).unwrap();
+                        prop_assert_eq!(&buf[..read], &model.read_bytes(len)[..]);
+                    }
+                    Op::Skip(count) => {
+                        prop_assert_eq!(
+                            bb.skip_bits(count as u64).map_err(failure),
+                            model.skip_bits(count)
+                        )
+                    }
+                    Op::Align => {
+                        bb.align_to_byte();
+                        model.align_to_byte();
+                    }
+                }
+                prop_assert_eq!(bb.bit_offset(), model.position as u64);
+                prop_assert_eq!(bb.remaining_bytes(), Some(model.remaining() as u64 / 8));
+            }
+        }
+    }
 }
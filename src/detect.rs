@@ -0,0 +1,173 @@
+//! Format sniffing over the first few bytes of a file.
+
+#[cfg(feature = "gpif")]
+use std::io;
+
+use gpx::{self, GpxFileType};
+#[cfg(feature = "gpif")]
+use gpx::Archive;
+#[cfg(feature = "gpif")]
+use gpx::score::{self, Score};
+
+/// Legacy files start with a length byte and a version string padded to 30 bytes.
+const LEGACY_VERSION_LEN: usize = 30;
+const LEGACY_VERSION_PREFIXES: [&'static str; 3] = [
+    "FICHIER GUITAR PRO ",
+    "FICHIER GUITARE PRO ",
+    "CLIPBOARD GUITAR PRO ",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Detected {
+    GpxBcfs,
+    GpxBcfz,
+    /// Guitar Pro 7+ zip container.
+    GpZip,
+    /// Guitar Pro 1-5 file, with its version string, e.g. "FICHIER GUITAR PRO v4.06".
+    LegacyGp(String),
+    Unknown,
+}
+
+/// Identifies the container or legacy format of `data`.
+/// Only looks at the first 31 bytes and never panics on short input.
+pub fn detect(data: &[u8]) -> Detected {
+    match gpx::check_file_type(data) {
+        GpxFileType::BCFS => Detected::GpxBcfs,
+        GpxFileType::BCFZ => Detected::GpxBcfz,
+        GpxFileType::ZIP => Detected::GpZip,
+        GpxFileType::Unknown => match legacy_version(data) {
+            Some(version) => Detected::LegacyGp(version),
+            None => Detected::Unknown,
+        },
+    }
+}
+
+/// Reads the score out of any container `detect` recognizes.
+/// Legacy Guitar Pro 1-5 files are detected but not parsed, they come back as an error.
+#[cfg(feature = "gpif")]
+pub fn read_any(data: &[u8]) -> io::Result<Score> {
+    match detect(data) {
+        Detected::GpxBcfs | Detected::GpxBcfz | Detected::GpZip => {
+            let files = try!(gpx::read_slice(data));
+            match files.score() {
+                Some(file) => score::parse(file.data()),
+                None => Err(invalid_data("Archive has no score.gpif".to_owned())),
+            }
+        }
+        Detected::LegacyGp(version) => Err(invalid_data(format!(
+            "Legacy Guitar Pro files are not supported yet ({})",
+            version
+        ))),
+        Detected::Unknown => Err(invalid_data("Unknown file format".to_owned())),
+    }
+}
+
+#[cfg(feature = "gpif")]
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn legacy_version(data: &[u8]) -> Option<String> {
+    let len = *data.first()? as usize;
+    if len > LEGACY_VERSION_LEN {
+        return None;
+    }
+    let version = String::from_utf8_lossy(data.get(1..1 + len)?);
+    if LEGACY_VERSION_PREFIXES
+        .iter()
+        .any(|prefix| version.starts_with(prefix))
+    {
+        Some(version.into_owned())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect, Detected};
+
+    fn legacy_header(version: &str) -> Vec<u8> {
+        let mut data = vec![version.len() as u8];
+        data.extend(version.as_bytes());
+        data.resize(31, 0);
+        data
+    }
+
+    #[test]
+    pub fn test_detect_containers() {
+        // Every file in test_data/.
+        assert_eq!(
+            detect(include_bytes!("../test_data/deep_nesting.gpx")),
+            Detected::GpxBcfs
+        );
+        assert_eq!(
+            detect(include_bytes!("../test_data/huge_entry.gp")),
+            Detected::GpZip
+        );
+        assert_eq!(
+            detect(include_bytes!("../test_data/two_tracks.gp")),
+            Detected::GpZip
+        );
+        assert_eq!(
+            detect(include_bytes!("../test_data/two_tracks.gpif")),
+            Detected::Unknown
+        );
+        assert_eq!(
+            detect(include_bytes!("../test_data/two_tracks.gpx")),
+            Detected::GpxBcfz
+        );
+    }
+
+    #[test]
+    pub fn test_detect_legacy() {
+        assert_eq!(
+            detect(&legacy_header("FICHIER GUITAR PRO v4.06")),
+            Detected::LegacyGp("FICHIER GUITAR PRO v4.06".to_owned())
+        );
+        assert_eq!(
+            detect(&legacy_header("FICHIER GUITARE PRO v1.04")),
+            Detected::LegacyGp("FICHIER GUITARE PRO v1.04".to_owned())
+        );
+        let mut too_long = legacy_header("FICHIER GUITAR PRO v4.06");
+        too_long[0] = 31;
+        assert_eq!(detect(&too_long), Detected::Unknown);
+    }
+
+    #[test]
+    pub fn test_detect_short_and_garbage() {
+        let header = legacy_header("FICHIER GUITAR PRO v5.00");
+        for len in 0..header.len() {
+            let expected = if len > 24 {
+                Detected::LegacyGp("FICHIER GUITAR PRO v5.00".to_owned())
+            } else {
+                Detected::Unknown
+            };
+            assert_eq!(detect(&header[..len]), expected);
+        }
+        assert_eq!(detect(&[]), Detected::Unknown);
+        assert_eq!(detect(b"BCF"), Detected::Unknown);
+        assert_eq!(detect(&[0xde, 0xad, 0xbe, 0xef, 0x00]), Detected::Unknown);
+        assert_eq!(detect(&[0xff; 64]), Detected::Unknown);
+    }
+
+    #[cfg(feature = "gpif")]
+    #[test]
+    pub fn test_read_any() {
+        use super::read_any;
+        let score = read_any(include_bytes!("../test_data/two_tracks.gpx")).unwrap();
+        assert_eq!(score.title, "Two Tracks");
+        assert_eq!(score.tracks.len(), 2);
+        assert_eq!(score.master_bars.len(), 2);
+        #[cfg(feature = "zip")]
+        assert_eq!(
+            read_any(include_bytes!("../test_data/two_tracks.gp")).unwrap(),
+            score
+        );
+
+        let err = read_any(&legacy_header("FICHIER GUITAR PRO v4.06")).unwrap_err();
+        assert!(err.to_string().contains("FICHIER GUITAR PRO v4.06"));
+        assert!(read_any(include_bytes!("../test_data/two_tracks.gpif")).is_err());
+        assert!(read_any(&[]).is_err());
+    }
+}
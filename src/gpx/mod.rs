@@ -1,28 +1,112 @@
 use std::io::{self, Cursor, Read};
 use std::cmp;
+use std::collections::HashMap;
 use std::iter;
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
 use super::bitbuffer;
 
+#[cfg(feature = "gpif")]
+pub mod score;
+
 #[derive(Debug, Clone)]
 pub enum GpxFileType {
     BCFS,
     BCFZ,
+    /// Guitar Pro 7+ `.gp` file, a plain zip archive.
+    ZIP,
     Unknown,
 }
 
 impl Copy for GpxFileType {}
 
-#[derive(Debug, RustcEncodable)]
+#[derive(Debug)]
+#[cfg_attr(feature = "rustc-serialize", derive(RustcEncodable))]
 pub struct File {
     file_name: String,
     file_data: Vec<u8>,
 }
 
+fn format_error(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// What an archive entry holds, see `File::kind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Score,
+    Stylesheet,
+    PartConfiguration,
+    LayoutConfiguration,
+    Audio,
+    Other,
+}
+
+impl File {
+    pub fn new(file_name: String, file_data: Vec<u8>) -> File {
+        File {
+            file_name: file_name,
+            file_data: file_data,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.file_data
+    }
+
+    /// Guesses the entry kind from its name, falling back to the leading bytes for audio.
+    pub fn kind(&self) -> FileKind {
+        // GP7 zips keep everything under Content/, GPX archives are flat.
+        let base_name = self.file_name
+            .rsplit('/')
+            .next()
+            .unwrap_or("")
+            .to_lowercase();
+        match base_name.as_str() {
+            "score.gpif" | "score.gpx" => FileKind::Score,
+            "binarystylesheet" | "stylesheet" => FileKind::Stylesheet,
+            "partconfiguration" => FileKind::PartConfiguration,
+            "layoutconfiguration" => FileKind::LayoutConfiguration,
+            _ if base_name.ends_with(".gpif") => FileKind::Score,
+            _ if is_audio(&base_name, &self.file_data) => FileKind::Audio,
+            _ => FileKind::Other,
+        }
+    }
+}
+
+fn is_audio(name: &str, data: &[u8]) -> bool {
+    let extensions = [".mp3", ".wav", ".ogg", ".flac", ".aif", ".aiff", ".m4a"];
+    if extensions.iter().any(|extension| name.ends_with(extension)) {
+        return true;
+    }
+    data.starts_with(b"RIFF") || data.starts_with(b"ID3") || data.starts_with(b"OggS")
+        || data.starts_with(b"fLaC")
+}
+
+/// Lookups over the entries returned by `read`.
+pub trait Archive {
+    /// The `score.gpif` entry, if the archive has one.
+    fn score(&self) -> Option<&File>;
+}
+
+impl Archive for [File] {
+    fn score(&self) -> Option<&File> {
+        self.iter().find(|file| file.kind() == FileKind::Score)
+    }
+}
+
 pub fn read(data: Vec<u8>) -> io::Result<Vec<File>> {
+    read_slice(&data)
+}
+
+/// Like `read`, but borrows the file, e.g. from a memory map.
+pub fn read_slice(data: &[u8]) -> io::Result<Vec<File>> {
     debug!("Reading file...");
-    match check_file_type(&data) {
+    match check_file_type(data) {
         GpxFileType::BCFZ => {
             debug!("File type BCFZ");
             let bcfs_data = try!(decompress_bcfz(&data[4..]));
@@ -35,7 +119,7 @@ pub fn read(data: Vec<u8>) -> io::Result<Vec<File>> {
                     io::ErrorKind::Other,
                     "BCFZ in BCFZ, weird...",
                 )),
-                GpxFileType::Unknown => Err(io::Error::new(
+                GpxFileType::ZIP | GpxFileType::Unknown => Err(io::Error::new(
                     io::ErrorKind::Other,
                     "BCFZ file didn't contain BCFS inside",
                 )),
@@ -45,22 +129,150 @@ pub fn read(data: Vec<u8>) -> io::Result<Vec<File>> {
             debug!("File type BCFS");
             decompress_bcfs(&data[4..])
         }
-        GpxFileType::Unknown => Err(io::Error::new(io::ErrorKind::Other, "Unknown file type")),
+        GpxFileType::ZIP => {
+            debug!("File type ZIP");
+            read_zip(data)
+        }
+        GpxFileType::Unknown => Err(io::Error::new(
+            io::ErrorKind::Other,
+            "Unknown file type",
+        )),
     }
 }
 
+/// Names and sizes of the files in an archive, without copying their data out.
+/// BCFZ archives still have to be decompressed to get to the BCFS index.
+pub fn list(data: &[u8]) -> io::Result<Vec<(String, usize)>> {
+    let bcfs_data;
+    let bcfs = match check_file_type(data) {
+        GpxFileType::BCFZ => {
+            bcfs_data = try!(decompress_bcfz(&data[4..]));
+            match check_file_type(&bcfs_data) {
+                GpxFileType::BCFS => &bcfs_data[4..],
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "BCFZ file didn't contain BCFS inside",
+                    ))
+                }
+            }
+        }
+        GpxFileType::BCFS => &data[4..],
+        GpxFileType::ZIP => return list_zip(data),
+        GpxFileType::Unknown => {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Unknown file type",
+            ))
+        }
+    };
+    let entries = try!(read_bcfs_entries(bcfs, false, &BcfsLayout::default()));
+    Ok(entries
+        .into_iter()
+        .map(|entry| (entry.file_name, entry.file_size))
+        .collect())
+}
+
+/// Like `read`, but takes the file from a reader, e.g. a `std::fs::File`.
+/// Unknown file types are rejected after the 4 byte magic, without reading the rest.
+pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Vec<File>> {
+    let mut data = Vec::with_capacity(4);
+    try!(reader.by_ref().take(4).read_to_end(&mut data));
+    if let GpxFileType::Unknown = check_file_type(&data) {
+        return Err(io::Error::new(io::ErrorKind::Other, "Unknown file type"));
+    }
+    try!(reader.read_to_end(&mut data));
+    read_slice(&data)
+}
+
 pub fn check_file_type(data: &[u8]) -> GpxFileType {
+    if data.len() < 4 {
+        return GpxFileType::Unknown;
+    }
     match (data[0], data[1], data[2], data[3]) {
         (0x42, 0x43, 0x46, 0x53) => GpxFileType::BCFS,
         (0x42, 0x43, 0x46, 0x5a) => GpxFileType::BCFZ,
+        (0x50, 0x4b, 0x03, 0x04) => GpxFileType::ZIP,
         _ => GpxFileType::Unknown,
     }
 }
 
+#[cfg(feature = "zip")]
+pub fn read_zip(data: &[u8]) -> io::Result<Vec<File>> {
+    let mut archive = try!(zip::ZipArchive::new(Cursor::new(data)));
+    let mut files = vec![];
+    for index in 0..archive.len() {
+        let mut entry = try!(archive.by_index(index));
+        if entry.is_dir() {
+            continue;
+        }
+        // The declared size is untrusted, cap the reserve like the BCFZ length header.
+        let reserve = cmp::min(entry.size(), BCFZ_RESERVE_LIMIT as u64) as usize;
+        let mut file_data = Vec::with_capacity(reserve);
+        try!(entry.read_to_end(&mut file_data));
+        files.push(File {
+            file_name: entry.name().to_owned(),
+            file_data: file_data,
+        });
+    }
+    Ok(files)
+}
+
+#[cfg(not(feature = "zip"))]
+pub fn read_zip(_data: &[u8]) -> io::Result<Vec<File>> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "Zip container support requires the zip feature",
+    ))
+}
+
+#[cfg(feature = "zip")]
+fn list_zip(data: &[u8]) -> io::Result<Vec<(String, usize)>> {
+    let mut archive = try!(zip::ZipArchive::new(Cursor::new(data)));
+    let mut files = vec![];
+    for index in 0..archive.len() {
+        let entry = try!(archive.by_index(index));
+        if !entry.is_dir() {
+            files.push((entry.name().to_owned(), entry.size() as usize));
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(not(feature = "zip"))]
+fn list_zip(_data: &[u8]) -> io::Result<Vec<(String, usize)>> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "Zip container support requires the zip feature",
+    ))
+}
+
+/// Upper bound for the up-front allocation, the length header comes straight from the file.
+const BCFZ_RESERVE_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Chunks in a row allowed to add no output before the stream is considered broken,
+/// e.g. zero length runs, which would otherwise be repeated forever.
+const BCFZ_MAX_EMPTY_CHUNKS: usize = 256;
+
 pub fn decompress_bcfz(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decompressed_data = vec![];
+    try!(decompress_bcfz_into(data, &mut decompressed_data));
+    Ok(decompressed_data)
+}
+
+/// Like `decompress_bcfz`, but reuses `decompressed_data`, which is cleared first.
+pub fn decompress_bcfz_into(data: &[u8], decompressed_data: &mut Vec<u8>) -> io::Result<()> {
     let mut bb = bitbuffer::BitBuffer::new(&data);
-    let expected_decompressed_data_len = try!(bb.read_i32::<LittleEndian>()) as usize;
-    let mut decompressed_data: Vec<u8> = Vec::with_capacity(expected_decompressed_data_len);
+    let expected_decompressed_data_len = try!(bb.read_i32::<LittleEndian>());
+    if expected_decompressed_data_len < 0 {
+        return Err(format_error(format!(
+            "Negative BCFZ decompressed length {}",
+            expected_decompressed_data_len
+        )));
+    }
+    let expected_decompressed_data_len = expected_decompressed_data_len as usize;
+    decompressed_data.clear();
+    decompressed_data.reserve(cmp::min(expected_decompressed_data_len, BCFZ_RESERVE_LIMIT));
     debug!(
         "Expected decompressed_data len: {}",
         expected_decompressed_data_len
@@ -71,11 +283,16 @@ pub fn decompress_bcfz(data: &[u8]) -> io::Result<Vec<u8>> {
         bb: &mut bitbuffer::BitBuffer,
         decompressed_data: &mut Vec<u8>,
     ) -> io::Result<()> {
-        let len = try!(bb.read_bits_reversed(2));
-        let mut buf: Vec<_> = iter::repeat(0u8).take(len).collect();
-        try!(bb.read(&mut buf));
-        decompressed_data.extend(buf);
-        Ok(())
+        let chunk_start = bb.bit_offset();
+        let len = try!(bb.read_bits_reversed_u32(2)) as usize;
+        let start = decompressed_data.len();
+        decompressed_data.resize(start + len, 0);
+        bb.read_exact(&mut decompressed_data[start..]).map_err(|e| {
+            format_error(format!(
+                "Truncated uncompressed chunk at bit {}, expected {} bytes: {}",
+                chunk_start, len, e
+            ))
+        })
     }
 
     #[inline]
@@ -83,98 +300,513 @@ pub fn decompress_bcfz(data: &[u8]) -> io::Result<Vec<u8>> {
         bb: &mut bitbuffer::BitBuffer,
         decompressed_data: &mut Vec<u8>,
     ) -> io::Result<()> {
-        let word_size = try!(bb.read_bits(4));
-        let offset = try!(bb.read_bits_reversed(word_size));
-        let len = try!(bb.read_bits_reversed(word_size));
-        assert!(decompressed_data.len() >= offset);
-        let source_position = decompressed_data.len() - offset;
-        let to_read = cmp::min(len, offset);
-        let slice = &decompressed_data[source_position..source_position + to_read].to_vec();
-        decompressed_data.extend(slice);
+        let chunk_start = bb.bit_offset();
+        let word_size: u32 = try!(bb.read_bits_u32(4));
+        let chunk_error = |e: bitbuffer::BitError| {
+            format_error(format!(
+                "Invalid compressed chunk at bit {} (word size {}): {}",
+                chunk_start,
+                word_size,
+                e
+            ))
+        };
+        let offset: u32 = try!(bb.read_bits_reversed_u32(word_size as usize).map_err(&chunk_error));
+        let len: u32 = try!(bb.read_bits_reversed_u32(word_size as usize).map_err(&chunk_error));
+        if offset as usize > decompressed_data.len() {
+            return Err(format_error(format!(
+                "Invalid compressed chunk at bit {}: offset {} reaches before the start, \
+                 only {} bytes decompressed",
+                chunk_start,
+                offset,
+                decompressed_data.len()
+            )));
+        }
+        let source_position = decompressed_data.len() - offset as usize;
+        let to_read = cmp::min(len, offset) as usize;
+        decompressed_data.extend_from_within(source_position..source_position + to_read);
         Ok(())
     }
 
+    let mut empty_chunks = 0;
     while decompressed_data.len() < expected_decompressed_data_len {
-        let bit = try!(bb.read_bit());
-        match bit {
-            0 => try!(read_uncompressed_chunk(&mut bb, &mut decompressed_data)),
-            1 => try!(read_compressed_chunk(&mut bb, &mut decompressed_data)),
+        let chunk_start = bb.bit_offset();
+        let len_before = decompressed_data.len();
+        // Files may stop short of the declared length, with the last byte padded by zeros.
+        // Zero bits only decode to empty uncompressed chunks, so that's a clean end.
+        let padding = (data.len() as u64 * 8).saturating_sub(chunk_start);
+        if padding < 8 && bb.peek_bits(padding as usize).ok() == Some(0) {
+            warn!(
+                "BCFZ stream ended after {} of {} bytes",
+                len_before, expected_decompressed_data_len
+            );
+            break;
+        }
+        match try!(bb.read_bit()) {
+            0 => try!(read_uncompressed_chunk(&mut bb, decompressed_data)),
+            1 => try!(read_compressed_chunk(&mut bb, decompressed_data)),
             _ => unreachable!(),
         }
+        if decompressed_data.len() > len_before {
+            empty_chunks = 0;
+        } else {
+            empty_chunks += 1;
+            if empty_chunks >= BCFZ_MAX_EMPTY_CHUNKS {
+                return Err(format_error(format!(
+                    "BCFZ stream stuck at bit {}: {} chunks in a row produced no data, \
+                     {} of {} bytes decompressed",
+                    chunk_start,
+                    empty_chunks,
+                    decompressed_data.len(),
+                    expected_decompressed_data_len
+                )));
+            }
+        }
     }
     debug!(
         "Successfully decompressed data. Len: {}, Expected len: {}",
         decompressed_data.len(),
         expected_decompressed_data_len
     );
-    Ok(decompressed_data)
+    Ok(())
+}
+
+/// Longest back reference offset a 4 bit word size can describe.
+const BCFZ_MAX_OFFSET: usize = (1 << 15) - 1;
+/// Shorter matches are cheaper to store as uncompressed chunks.
+const BCFZ_MIN_MATCH: usize = 3;
+/// How many earlier occurrences of a 3 byte prefix to try per position.
+const BCFZ_MAX_CHAIN: usize = 64;
+
+/// Inverse of `decompress_bcfz`, output starts with the length header (no `BCFZ` magic).
+/// Greedy LZ77 with hash chains: good enough, not as tight as Guitar Pro's own output.
+pub fn compress_bcfz(data: &[u8]) -> Vec<u8> {
+    #[inline]
+    fn insert_prefix<'a>(
+        data: &'a [u8],
+        position: usize,
+        heads: &mut HashMap<&'a [u8], usize>,
+        previous: &mut [Option<usize>],
+    ) {
+        if position + BCFZ_MIN_MATCH <= data.len() {
+            let prefix = &data[position..position + BCFZ_MIN_MATCH];
+            previous[position] = heads.insert(prefix, position);
+        }
+    }
+
+    #[inline]
+    fn write_uncompressed_chunks(bw: &mut bitbuffer::BitWriter, literals: &[u8]) {
+        for chunk in literals.chunks(3) {
+            bw.write_bit(0);
+            bw.write_bits_reversed(chunk.len(), 2);
+            bw.write_bytes(chunk);
+        }
+    }
+
+    let mut bw = bitbuffer::BitWriter::new();
+    let len = data.len() as u32;
+    bw.write_bytes(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+
+    // Most recent position of each 3 byte prefix, and links to the previous one.
+    let mut heads: HashMap<&[u8], usize> = HashMap::new();
+    let mut previous: Vec<Option<usize>> = vec![None; data.len()];
+    let mut literals_start = 0;
+    let mut position = 0;
+    while position < data.len() {
+        let mut best_len = 0;
+        let mut best_offset = 0;
+        if position + BCFZ_MIN_MATCH <= data.len() {
+            let mut candidate = heads
+                .get(&data[position..position + BCFZ_MIN_MATCH])
+                .cloned();
+            let mut chain = 0;
+            while let Some(source) = candidate {
+                let offset = position - source;
+                if offset > BCFZ_MAX_OFFSET || chain == BCFZ_MAX_CHAIN {
+                    break;
+                }
+                // The decoder never copies more than `offset` bytes.
+                let max_len = cmp::min(offset, data.len() - position);
+                let len = data[source..source + max_len]
+                    .iter()
+                    .zip(&data[position..])
+                    .take_while(|&(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_offset = offset;
+                }
+                candidate = previous[source];
+                chain += 1;
+            }
+        }
+
+        if best_len >= BCFZ_MIN_MATCH {
+            write_uncompressed_chunks(&mut bw, &data[literals_start..position]);
+            let word_size = 64 - (best_offset as u64).leading_zeros() as usize;
+            bw.write_bit(1);
+            bw.write_bits(word_size, 4);
+            bw.write_bits_reversed(best_offset, word_size);
+            bw.write_bits_reversed(best_len, word_size);
+            for covered in position..position + best_len {
+                insert_prefix(data, covered, &mut heads, &mut previous);
+            }
+            position += best_len;
+            literals_start = position;
+        } else {
+            insert_prefix(data, position, &mut heads, &mut previous);
+            position += 1;
+        }
+    }
+    write_uncompressed_chunks(&mut bw, &data[literals_start..]);
+    bw.into_bytes()
+}
+
+/// BCFS containers are split into sectors, sector 0 is unused. A sector starting
+/// with the i32 `2` is the index sector of a file, the offsets below are relative to it.
+pub const SECTOR_SIZE: usize = 0x1000;
+/// Zero padded file name.
+pub const FILE_NAME_OFFSET: usize = 4;
+pub const FILE_NAME_MAX_LEN: usize = 127;
+/// i32 file size in bytes.
+pub const FILE_SIZE_OFFSET: usize = 0x8C;
+/// Zero terminated list of the i32 sector numbers holding the file data.
+pub const BLOCK_TABLE_OFFSET: usize = 0x94;
+
+/// Where `decompress_bcfs_with_layout` looks for things, defaults to the constants above.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BcfsLayout {
+    pub sector_size: usize,
+    pub file_name_offset: usize,
+    pub file_name_max_len: usize,
+    pub file_size_offset: usize,
+    pub block_table_offset: usize,
+}
+
+impl Default for BcfsLayout {
+    fn default() -> BcfsLayout {
+        BcfsLayout {
+            sector_size: SECTOR_SIZE,
+            file_name_offset: FILE_NAME_OFFSET,
+            file_name_max_len: FILE_NAME_MAX_LEN,
+            file_size_offset: FILE_SIZE_OFFSET,
+            block_table_offset: BLOCK_TABLE_OFFSET,
+        }
+    }
+}
+
+/// Largest `BcfsLayout::sector_size`, keeps sector offsets of 32 bit block numbers in a u64.
+const MAX_SECTOR_SIZE: usize = 1 << 24;
+
+impl BcfsLayout {
+    /// Every field has to fit inside the index sector, which starts with its i32 type.
+    fn validate(&self) -> io::Result<()> {
+        let fits = |offset: usize, len: usize| match offset.checked_add(len) {
+            Some(end) => end <= self.sector_size,
+            None => false,
+        };
+        let problem = if self.sector_size < 4 || self.sector_size > MAX_SECTOR_SIZE {
+            Some("sector size")
+        } else if !fits(self.file_name_offset, self.file_name_max_len) {
+            Some("file name")
+        } else if !fits(self.file_size_offset, 4) {
+            Some("file size")
+        } else if !fits(self.block_table_offset, 4) {
+            Some("block table")
+        } else {
+            None
+        };
+        match problem {
+            Some(field) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid BCFS layout, {} doesn't fit: {:?}", field, self),
+            )),
+            None => Ok(()),
+        }
+    }
 }
 
+/// Files whose recorded size is larger than their blocks hold are skipped with a warning.
 pub fn decompress_bcfs(data: &[u8]) -> io::Result<Vec<File>> {
+    decompress_bcfs_with_layout(data, &BcfsLayout::default())
+}
+
+/// Like `decompress_bcfs`, but a file with a size mismatch is an error.
+pub fn decompress_bcfs_strict(data: &[u8]) -> io::Result<Vec<File>> {
+    let layout = BcfsLayout::default();
+    let entries = try!(read_bcfs_entries(data, true, &layout));
+    Ok(read_bcfs_files(data, entries, layout.sector_size))
+}
+
+/// Like `decompress_bcfs`, with the sector size and index offsets taken from `layout`.
+pub fn decompress_bcfs_with_layout(data: &[u8], layout: &BcfsLayout) -> io::Result<Vec<File>> {
+    try!(layout.validate());
+    let entries = try!(read_bcfs_entries(data, false, layout));
+    Ok(read_bcfs_files(data, entries, layout.sector_size))
+}
+
+/// Index sector contents of one BCFS file.
+struct BcfsEntry {
+    file_name: String,
+    file_size: usize,
+    blocks: Vec<u64>,
+}
+
+fn read_bcfs_entries(
+    data: &[u8],
+    strict: bool,
+    layout: &BcfsLayout,
+) -> io::Result<Vec<BcfsEntry>> {
     let data_len = data.len() as u64;
-    let sector_size = 0x1000u64;
+    let sector_size = layout.sector_size as u64;
     let mut reader = Cursor::new(data);
     let mut offset = 0u64;
-    let mut files = vec![];
+    let mut entries = vec![];
+    let mut file_index = 0;
 
     loop {
         offset = offset + sector_size;
         if offset + 3 >= data_len {
             break;
         }
-        reader.set_position(offset);
-        if try!(reader.read_i32::<LittleEndian>()) == 2 {
-            let index_file_name = offset + 4;
-            let index_file_size = offset + 0x8C;
-            let index_of_block = offset + 0x94;
-            let mut file_data: Vec<u8> = Vec::new();
+        if try!(read_le_i32(&mut reader, offset)) == 2 {
+            let index_file_name = offset + layout.file_name_offset as u64;
+            let index_file_size = offset + layout.file_size_offset as u64;
+            let index_of_block = offset + layout.block_table_offset as u64;
+            let mut blocks = vec![];
+            // Bytes the blocks actually hold, the last sector may be cut short.
+            let mut available = 0usize;
 
-            let mut block;
-            let mut block_count = 0u64;
             loop {
-                reader.set_position((index_of_block + (4 * block_count)));
-                block = try!(reader.read_i32::<LittleEndian>());
+                let block_entry = index_of_block + 4 * blocks.len() as u64;
+                let block = try!(read_le_i32(&mut reader, block_entry));
                 if block == 0 {
                     break;
                 }
-                offset = (block as u64) * sector_size;
-                reader.set_position(offset);
-                let mut buf: Vec<_> = iter::repeat(0u8).take(sector_size as usize).collect();
-                try!(reader.read(&mut buf));
-                file_data.extend(buf);
-                block_count += 1;
+                let block_offset = (block as u32 as u64) * sector_size;
+                available += cmp::min(data_len.saturating_sub(block_offset), sector_size) as usize;
+                blocks.push(block_offset);
+                // Data sectors follow their index sector, continue scanning after them.
+                // Never go back though, or a block pointing before its index sector
+                // would have the same index read over and over.
+                offset = cmp::max(offset, block_offset);
             }
 
-            reader.set_position(index_file_size);
-            let file_size = try!(reader.read_i32::<LittleEndian>()) as usize;
-            if file_size <= file_data.len() {
-                reader.set_position(index_file_name);
-                let mut buf: Vec<_> = iter::repeat(0u8).take(127).collect();
-                try!(reader.read(&mut buf));
-                let file_name = String::from_utf8_lossy(&buf)
-                    .trim_right_matches('\0')
-                    .to_owned();
-                reader.set_position(index_file_name);
-                let file_bytes = &file_data[0..file_size];
-                files.push(File {
-                    file_name: file_name.clone(),
-                    file_data: file_bytes.to_vec(),
+            let file_size = try!(read_le_i32(&mut reader, index_file_size)) as usize;
+            reader.set_position(index_file_name);
+            let mut buf: Vec<_> = iter::repeat(0u8).take(layout.file_name_max_len).collect();
+            try!(reader.read(&mut buf));
+            let file_name = String::from_utf8_lossy(&buf)
+                .trim_right_matches('\0')
+                .to_owned();
+            if file_size <= available {
+                entries.push(BcfsEntry {
+                    file_name: file_name,
+                    file_size: file_size,
+                    blocks: blocks,
                 });
+            } else {
+                let message = format!(
+                    "File {} ({:?}) size is {} bytes, but its {} blocks only hold {}",
+                    file_index,
+                    file_name,
+                    file_size,
+                    blocks.len(),
+                    available
+                );
+                if strict {
+                    return Err(format_error(message));
+                }
+                warn!("Skipping BCFS entry. {}", message);
             }
+            file_index += 1;
         }
     }
-    Ok(files)
+    Ok(entries)
+}
+
+fn read_le_i32(reader: &mut Cursor<&[u8]>, position: u64) -> io::Result<i32> {
+    reader.set_position(position);
+    reader.read_i32::<LittleEndian>()
+}
+
+fn read_bcfs_file(data: &[u8], entry: BcfsEntry, sector_size: usize) -> File {
+    let mut file_data: Vec<u8> = Vec::with_capacity(entry.file_size);
+    for &block_offset in &entry.blocks {
+        let start = cmp::min(block_offset, data.len() as u64) as usize;
+        let end = cmp::min(start + sector_size, data.len());
+        file_data.extend_from_slice(&data[start..end]);
+    }
+    file_data.truncate(entry.file_size);
+    File {
+        file_name: entry.file_name,
+        file_data: file_data,
+    }
+}
+
+/// Builds a BCFS container (including the magic) that `decompress_bcfs` reads back.
+/// Sector 0 is left empty, then each file gets its index sector followed by its data sectors.
+pub fn write_bcfs(files: &[File]) -> Vec<u8> {
+    let sector_size = SECTOR_SIZE;
+    let mut data = vec![0u8; sector_size];
+    for file in files {
+        let block_count = (file.file_data.len() + sector_size - 1) / sector_size;
+        // The block list is zero terminated and may run past the index sector.
+        let index_len = BLOCK_TABLE_OFFSET + 4 * (block_count + 1);
+        let index_sectors = (index_len + sector_size - 1) / sector_size;
+        let index = data.len();
+        let first_block = index / sector_size + index_sectors;
+        data.resize(index + (index_sectors + block_count) * sector_size, 0);
+
+        write_le_i32(&mut data[index..], 2);
+        let name = file.file_name.as_bytes();
+        let name_len = cmp::min(name.len(), FILE_NAME_MAX_LEN);
+        let name_start = index + FILE_NAME_OFFSET;
+        data[name_start..name_start + name_len].copy_from_slice(&name[..name_len]);
+        write_le_i32(&mut data[index + FILE_SIZE_OFFSET..], file.file_data.len() as i32);
+        for block in 0..block_count {
+            write_le_i32(
+                &mut data[index + BLOCK_TABLE_OFFSET + 4 * block..],
+                (first_block + block) as i32,
+            );
+        }
+        let data_start = first_block * sector_size;
+        data[data_start..data_start + file.file_data.len()].copy_from_slice(&file.file_data);
+    }
+    let mut bcfs = b"BCFS".to_vec();
+    bcfs.extend(data);
+    bcfs
+}
+
+fn write_le_i32(buf: &mut [u8], value: i32) {
+    LittleEndian::write_i32(&mut buf[..4], value);
+}
+
+#[cfg(not(feature = "parallel"))]
+fn read_bcfs_files(data: &[u8], entries: Vec<BcfsEntry>, sector_size: usize) -> Vec<File> {
+    entries
+        .into_iter()
+        .map(|entry| read_bcfs_file(data, entry, sector_size))
+        .collect()
+}
+
+/// Sector copying is independent per file, so spread it over the rayon pool.
+/// `collect` keeps the entries in sector order.
+#[cfg(feature = "parallel")]
+fn read_bcfs_files(data: &[u8], entries: Vec<BcfsEntry>, sector_size: usize) -> Vec<File> {
+    use rayon::prelude::*;
+    entries
+        .into_par_iter()
+        .map(|entry| read_bcfs_file(data, entry, sector_size))
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
-    #[allow(unreachable_code)]
+    use proptest::prelude::*;
+
+    // Length 5, then an uncompressed chunk "abc" and a back reference copying "ab".
+    const BCFZ_ABCAB: [u8; 9] = [5, 0, 0, 0, 0x6c, 0x2c, 0x4c, 0x72, 0xd0];
+
     #[test]
     pub fn test_load_bcfz() {
-        return;
-        //NOT IMPLEMENTED. Need good source data example.
-        // let data = vec![];
-        // assert_eq!(super::decompress_bcfz(&data).unwrap(), vec![]);
+        assert_eq!(super::decompress_bcfz(&BCFZ_ABCAB).unwrap(), b"abcab".to_vec());
+        assert!(super::decompress_bcfz(&[0xff, 0xff, 0xff, 0xff, 0x6c]).is_err());
+    }
+
+    #[test]
+    pub fn test_decompress_bcfz_truncated() {
+        use std::io::ErrorKind;
+        // Every proper prefix of the body cuts a chunk short.
+        for len in 5..BCFZ_ABCAB.len() {
+            let err = super::decompress_bcfz(&BCFZ_ABCAB[..len]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+        let err = super::decompress_bcfz(&BCFZ_ABCAB[..6]).unwrap_err();
+        assert!(err.to_string().contains("Truncated uncompressed chunk at bit 33"));
+        let err = super::decompress_bcfz(&BCFZ_ABCAB[..8]).unwrap_err();
+        assert!(err.to_string().contains("Invalid compressed chunk"));
+        assert!(err.to_string().contains("2 bits requested at bit 64"));
+        // Ending between chunks gives what was decoded so far, even short of the length.
+        assert_eq!(super::decompress_bcfz(&BCFZ_ABCAB[..4]).unwrap(), vec![]);
+        let mut padded = BCFZ_ABCAB[..8].to_vec();
+        padded[7] &= 0xe0;
+        assert_eq!(super::decompress_bcfz(&padded).unwrap(), b"abc".to_vec());
+        // A header cut short is an I/O error of its own.
+        assert!(super::decompress_bcfz(&BCFZ_ABCAB[..3]).is_err());
+    }
+
+    #[test]
+    pub fn test_decompress_bcfz_offset_out_of_range() {
+        // A back reference of offset 1 before anything was decompressed.
+        let err = super::decompress_bcfz(&[3, 0, 0, 0, 0x95, 0x00]).unwrap_err();
+        assert!(err.to_string().contains("offset 1 reaches before the start"));
+    }
+
+    #[test]
+    pub fn test_decompress_bcfz_empty_chunks() {
+        use super::bitbuffer::BitWriter;
+        // A few empty chunks of either kind are tolerated.
+        let mut bw = BitWriter::new();
+        bw.write_bytes(&[1, 0, 0, 0]);
+        for _ in 0..10 {
+            bw.write_bit(0);
+            bw.write_bits_reversed(0, 2);
+            bw.write_bit(1);
+            bw.write_bits(0, 4);
+        }
+        bw.write_bit(0);
+        bw.write_bits_reversed(1, 2);
+        bw.write_bytes(b"x");
+        assert_eq!(super::decompress_bcfz(&bw.into_bytes()).unwrap(), b"x".to_vec());
+
+        // An endless run of them is an error instead of a hang. Zero bits in the
+        // last byte are padding though, so run on past it.
+        let mut bw = BitWriter::new();
+        bw.write_bytes(&[1, 0, 0, 0]);
+        for _ in 0..super::BCFZ_MAX_EMPTY_CHUNKS + 8 {
+            bw.write_bit(0);
+            bw.write_bits_reversed(0, 2);
+        }
+        let err = super::decompress_bcfz(&bw.into_bytes()).unwrap_err();
+        assert!(err.to_string().contains("produced no data"));
+    }
+
+    #[test]
+    pub fn test_compress_bcfz_round_trip() {
+        let mut seed = 12345u32;
+        let noise: Vec<u8> = (0..50000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        let text = b"<Beat><Rhythm ref=\"0\"/></Beat>".repeat(2000);
+        let mut long_distance = noise[..40000].to_vec();
+        long_distance.extend_from_slice(&noise[..1000]);
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![0],
+            vec![0; 3],
+            vec![0; 100000],
+            b"abcab".to_vec(),
+            noise,
+            text.clone(),
+            long_distance,
+        ];
+        for input in inputs {
+            let compressed = super::compress_bcfz(&input);
+            assert_eq!(super::decompress_bcfz(&compressed).unwrap(), input);
+        }
+        assert!(super::compress_bcfz(&text).len() < text.len() / 10);
+    }
+
+    #[test]
+    pub fn test_decompress_bcfz_into_reuses_buffer() {
+        let mut buffer = b"stale contents".to_vec();
+        super::decompress_bcfz_into(&BCFZ_ABCAB, &mut buffer).unwrap();
+        assert_eq!(buffer, b"abcab".to_vec());
+        super::decompress_bcfz_into(&BCFZ_ABCAB, &mut buffer).unwrap();
+        assert_eq!(buffer, b"abcab".to_vec());
     }
 
     #[test]
@@ -182,6 +814,7 @@ mod tests {
         use super::GpxFileType;
         let data_bcfs = [0x42, 0x43, 0x46, 0x53];
         let data_bcfz = [0x42, 0x43, 0x46, 0x5a];
+        let data_zip = [0x50, 0x4b, 0x03, 0x04];
         let data_random = [0xde, 0xad, 0xbe, 0xef];
         assert!(match super::check_file_type(&data_bcfs) {
             GpxFileType::BCFS => true,
@@ -191,9 +824,285 @@ mod tests {
             GpxFileType::BCFZ => true,
             _ => false,
         });
+        assert!(match super::check_file_type(&data_zip) {
+            GpxFileType::ZIP => true,
+            _ => false,
+        });
         assert!(match super::check_file_type(&data_random) {
             GpxFileType::Unknown => true,
             _ => false,
         });
     }
+
+    #[cfg(all(feature = "zip", feature = "gpif"))]
+    #[test]
+    pub fn test_read_zip() {
+        let data = include_bytes!("../../test_data/two_tracks.gp").to_vec();
+        let files = super::read(data).unwrap();
+        let names: Vec<_> = files.iter().map(|f| f.file_name.as_str()).collect();
+        assert_eq!(names, vec!["VERSION", "Content/score.gpif"]);
+        let score = super::score::parse(&files[1].file_data).unwrap();
+        assert_eq!(score.tracks.len(), 2);
+
+        // The zip64 entry claims 2^62 bytes, which mustn't be allocated up front.
+        let huge = include_bytes!("../../test_data/huge_entry.gp").to_vec();
+        let _ = super::read(huge);
+    }
+
+    #[test]
+    pub fn test_write_bcfs_round_trip() {
+        use super::File;
+        let big: Vec<u8> = (0..5 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let files = vec![
+            File::new("score.gpif".to_owned(), b"<GPIF/>".to_vec()),
+            File::new("empty".to_owned(), vec![]),
+            File::new("one_sector".to_owned(), vec![7; 0x1000]),
+            File::new("misc.xml".to_owned(), vec![1; 0x2345]),
+            File::new("big".to_owned(), big),
+        ];
+        let bcfs = super::write_bcfs(&files);
+        assert_eq!(&bcfs[..4], b"BCFS");
+        let read_back = super::decompress_bcfs(&bcfs[4..]).unwrap();
+        assert_eq!(read_back.len(), files.len());
+        for (written, read) in files.iter().zip(read_back.iter()) {
+            assert_eq!(written.file_name, read.file_name);
+            assert!(written.file_data == read.file_data);
+        }
+        assert_eq!(super::read(bcfs).unwrap().len(), files.len());
+    }
+
+    #[cfg(feature = "gpif")]
+    #[test]
+    pub fn test_read_gpx_score() {
+        use super::Archive;
+        let files = super::read(include_bytes!("../../test_data/two_tracks.gpx").to_vec()).unwrap();
+        assert_eq!(files.len(), 3);
+        let score = super::score::parse(files.score().unwrap().data()).unwrap();
+        assert_eq!(score.title, "Two Tracks");
+        assert_eq!(score.tracks.len(), 2);
+        assert_eq!(score.master_bars[0].bars[1].voices[0].beats[0].notes[1].fret, 7);
+    }
+
+    #[test]
+    pub fn test_read_from_file() {
+        use std::fs;
+        use std::io::Cursor;
+        let mut file = fs::File::open("test_data/two_tracks.gpx").unwrap();
+        let files = super::read_from(&mut file).unwrap();
+        let names: Vec<&str> = files.iter().map(|file| file.name()).collect();
+        let expected = super::read(include_bytes!("../../test_data/two_tracks.gpx").to_vec()).unwrap();
+        assert_eq!(names, expected.iter().map(|file| file.name()).collect::<Vec<_>>());
+        assert_eq!(files[0].data(), expected[0].data());
+        let borrowed = super::read_slice(include_bytes!("../../test_data/two_tracks.gpx")).unwrap();
+        assert_eq!(borrowed[0].data(), expected[0].data());
+
+        let mut gpif = fs::File::open("test_data/two_tracks.gpif").unwrap();
+        assert!(super::read_from(&mut gpif).is_err());
+        assert!(super::read_from(&mut Cursor::new(b"BCF".to_vec())).is_err());
+    }
+
+    #[test]
+    pub fn test_list() {
+        let data = include_bytes!("../../test_data/two_tracks.gpx");
+        let listing = super::list(data).unwrap();
+        assert!(listing.iter().any(|&(ref name, _)| name == "score.gpif"));
+        let files = super::read_slice(data).unwrap();
+        let expected: Vec<_> = files
+            .iter()
+            .map(|file| (file.name().to_owned(), file.data().len()))
+            .collect();
+        assert_eq!(listing, expected);
+        #[cfg(feature = "zip")]
+        assert!(super::list(include_bytes!("../../test_data/two_tracks.gp"))
+            .unwrap()
+            .iter()
+            .any(|&(ref name, _)| name.ends_with("score.gpif")));
+        assert!(super::list(b"BCF").is_err());
+    }
+
+    #[test]
+    pub fn test_file_kind() {
+        use super::{Archive, File, FileKind};
+        let file = |name: &str, data: &[u8]| File {
+            file_name: name.to_owned(),
+            file_data: data.to_vec(),
+        };
+        let files = vec![
+            file("BinaryStylesheet", b""),
+            file("PartConfiguration", b""),
+            file("LayoutConfiguration", b""),
+            file("Content/Assets/backing", b"ID3\x03"),
+            file("misc.xml", b"<xml/>"),
+        ];
+        let kinds: Vec<_> = files.iter().map(|f| f.kind()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                FileKind::Stylesheet,
+                FileKind::PartConfiguration,
+                FileKind::LayoutConfiguration,
+                FileKind::Audio,
+                FileKind::Other,
+            ]
+        );
+        assert!(files.score().is_none());
+
+        let with_score = vec![file("misc.xml", b""), file("Content/score.gpif", b"<GPIF/>")];
+        assert_eq!(with_score.score().unwrap().name(), "Content/score.gpif");
+    }
+
+    #[test]
+    pub fn test_decompress_bcfs_block_before_index() {
+        use super::write_le_i32;
+        // Sector 2 indexes a file stored back in sector 1, scanning goes on after sector 2.
+        let mut data = vec![0u8; 3 * 0x1000];
+        data[0x1000] = 7;
+        write_le_i32(&mut data[0x2000..], 2);
+        data[0x2004] = b'a';
+        write_le_i32(&mut data[0x208C..], 1);
+        write_le_i32(&mut data[0x2094..], 1);
+        let files = super::decompress_bcfs(&data).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].file_data, vec![7]);
+    }
+
+    #[test]
+    pub fn test_decompress_bcfs_with_layout() {
+        use super::{write_le_i32, BcfsLayout};
+        use std::io::ErrorKind;
+        // 0x100 byte sectors, with the name and size swapped around in the index.
+        let layout = BcfsLayout {
+            sector_size: 0x100,
+            file_name_offset: 0x10,
+            file_name_max_len: 8,
+            file_size_offset: 4,
+            block_table_offset: 0x20,
+        };
+        let mut data = vec![0u8; 3 * 0x100];
+        write_le_i32(&mut data[0x100..], 2);
+        data[0x110..0x11b].copy_from_slice(b"long_name.x");
+        write_le_i32(&mut data[0x104..], 3);
+        write_le_i32(&mut data[0x120..], 2);
+        data[0x200..0x203].copy_from_slice(b"abc");
+        let files = super::decompress_bcfs_with_layout(&data, &layout).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].file_name, "long_nam");
+        assert_eq!(files[0].file_data, b"abc".to_vec());
+
+        let bcfs = super::write_bcfs(&files);
+        let read_back = super::decompress_bcfs_with_layout(&bcfs[4..], &BcfsLayout::default());
+        assert_eq!(read_back.unwrap()[0].file_data, b"abc".to_vec());
+
+        assert!(BcfsLayout::default().validate().is_ok());
+        let invalid = [
+            BcfsLayout { sector_size: 0, ..layout },
+            BcfsLayout { sector_size: usize::MAX, ..layout },
+            BcfsLayout { file_name_max_len: usize::MAX, ..layout },
+            BcfsLayout { file_name_offset: 0xf9, ..layout },
+            BcfsLayout { file_size_offset: 0xfd, ..layout },
+            BcfsLayout { block_table_offset: usize::MAX, ..layout },
+        ];
+        for invalid in &invalid {
+            let err = super::decompress_bcfs_with_layout(&data, invalid).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    /// What the fuzz target does: read the archive, then parse the score inside.
+    #[cfg(feature = "gpif")]
+    fn read_and_parse(data: Vec<u8>) {
+        use super::Archive;
+        if let Ok(files) = super::read(data) {
+            if let Some(score) = files.score() {
+                let _ = super::score::parse(score.data());
+            }
+        }
+    }
+
+    #[cfg(not(feature = "gpif"))]
+    fn read_and_parse(data: Vec<u8>) {
+        let _ = super::read(data);
+    }
+
+    proptest! {
+        #[test]
+        fn read_never_panics(
+            magic in prop_oneof![Just(&b"BCFZ"[..]), Just(&b"BCFS"[..]), Just(&b"PK\x03\x04"[..])],
+            body in proptest::collection::vec(any::<u8>(), 0..0x3000),
+        ) {
+            let mut data = magic.to_vec();
+            data.extend(body);
+            read_and_parse(data);
+        }
+
+        /// Starts from the test archives and the crafted inputs the fuzzer found:
+        /// a zip64 entry declaring 2^62 bytes and a score nested 1000 levels deep.
+        #[test]
+        fn read_never_panics_on_damaged_archives(
+            archive in prop_oneof![
+                Just(&include_bytes!("../../test_data/two_tracks.gpx")[..]),
+                Just(&include_bytes!("../../test_data/huge_entry.gp")[..]),
+                Just(&include_bytes!("../../test_data/deep_nesting.gpx")[..]),
+            ],
+            damage in proptest::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 0..8),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let mut data = archive.to_vec();
+            for (index, byte) in damage {
+                let len = data.len();
+                data[index.index(len)] = byte;
+            }
+            let len = data.len();
+            data.truncate(cut.index(len) + 1);
+            read_and_parse(data);
+        }
+    }
+
+    #[test]
+    pub fn test_decompress_bcfs() {
+        use super::write_le_i32;
+        // Sector 1 indexes "a" stored in sectors 2 and 3, sector 4 indexes "b" in sector 5,
+        // sector 6 indexes "c" whose sector 7 is cut short by the end of the data.
+        let mut data = vec![0u8; 7 * 0x1000 + 2];
+        write_le_i32(&mut data[0x1000..], 2);
+        data[0x1004] = b'a';
+        write_le_i32(&mut data[0x108C..], 0x1000 + 3);
+        write_le_i32(&mut data[0x1094..], 2);
+        write_le_i32(&mut data[0x1098..], 3);
+        data[0x2000] = 1;
+        data[0x3000..0x3003].copy_from_slice(&[2, 3, 4]);
+        write_le_i32(&mut data[0x4000..], 2);
+        data[0x4004] = b'b';
+        write_le_i32(&mut data[0x408C..], 0x1000);
+        write_le_i32(&mut data[0x4094..], 5);
+        data[0x5fff] = 9;
+        write_le_i32(&mut data[0x6000..], 2);
+        data[0x6004] = b'c';
+        write_le_i32(&mut data[0x608C..], 4);
+        write_le_i32(&mut data[0x6094..], 7);
+        data[0x7000..0x7002].copy_from_slice(&[5, 6]);
+
+        let files = super::decompress_bcfs(&data).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].file_name, "a");
+        assert_eq!(files[0].file_data.len(), 0x1003);
+        assert_eq!(files[0].file_data[0], 1);
+        assert_eq!(&files[0].file_data[0x1000..], &[2, 3, 4]);
+        assert_eq!(files[1].file_name, "b");
+        assert_eq!(files[1].file_data.len(), 0x1000);
+        assert_eq!(files[1].file_data[0xfff], 9);
+
+        assert_eq!(
+            super::decompress_bcfs_strict(&data).unwrap_err().to_string(),
+            "File 2 (\"c\") size is 4 bytes, but its 1 blocks only hold 2"
+        );
+
+        // With "c" no longer truncated every file comes back.
+        data.resize(8 * 0x1000, 0);
+        let files = super::decompress_bcfs(&data).unwrap();
+        let names: Vec<_> = files.iter().map(|f| f.file_name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b", "c"]);
+        assert_eq!(files[2].file_data, vec![5, 6, 0, 0]);
+        assert_eq!(super::decompress_bcfs_strict(&data).unwrap().len(), 3);
+    }
 }
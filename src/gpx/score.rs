@@ -0,0 +1,953 @@
+//! Parser for `score.gpif`, the XML document holding the music inside a GPX archive.
+
+use std::cmp::{self, Ordering};
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+
+use xml::reader::{EventReader, XmlEvent};
+
+use super::format_error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoChange {
+    /// Zero based bar index.
+    pub bar: u32,
+    /// Offset inside the bar, as a fraction of the bar length.
+    pub position: f32,
+    pub bpm: f32,
+    /// Tempo changes gradually up to the next change instead of jumping.
+    pub linear: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track {
+    pub name: String,
+    /// Empty for tracks without strings, like drums.
+    pub tuning: Tuning,
+}
+
+/// MIDI pitches of the open strings, lowest string first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tuning(pub Vec<u8>);
+
+/// Standard six string guitar tuning, E2 A2 D3 G3 B3 E4.
+const STANDARD_TUNING: [u8; 6] = [40, 45, 50, 55, 59, 64];
+
+/// Named six string tunings as semitone offsets from `STANDARD_TUNING`.
+const TUNING_NAMES: [(&'static str, [i8; 6]); 5] = [
+    ("Standard", [0, 0, 0, 0, 0, 0]),
+    ("Drop D", [-2, 0, 0, 0, 0, 0]),
+    ("DADGAD", [-2, 0, 0, 0, -2, -2]),
+    ("Open G", [-2, -2, 0, 0, 0, -2]),
+    ("Half-step down", [-1, -1, -1, -1, -1, -1]),
+];
+
+impl Tuning {
+    /// Name of a common six string tuning, `None` for anything else.
+    pub fn name(&self) -> Option<&'static str> {
+        if self.0.len() != STANDARD_TUNING.len() {
+            return None;
+        }
+        let mut deltas = [0; 6];
+        for (delta, (&pitch, &standard)) in deltas
+            .iter_mut()
+            .zip(self.0.iter().zip(STANDARD_TUNING.iter()))
+        {
+            *delta = pitch as i16 - standard as i16;
+        }
+        TUNING_NAMES
+            .iter()
+            .find(|&&(_, ref offsets)| {
+                offsets.iter().zip(deltas.iter()).all(|(&o, &d)| o as i16 == d)
+            })
+            .map(|&(name, _)| name)
+    }
+}
+
+/// A column of the score: one `Bar` per track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MasterBar {
+    pub bars: Vec<Bar>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bar {
+    /// Voices in use, empty voice slots are dropped.
+    pub voices: Vec<Voice>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Voice {
+    pub beats: Vec<Beat>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Beat {
+    pub rhythm: Rhythm,
+    pub notes: Vec<Note>,
+}
+
+impl Beat {
+    pub fn is_rest(&self) -> bool {
+        self.notes.is_empty()
+    }
+}
+
+/// Ticks in a quarter note, matching the legacy Guitar Pro formats.
+pub const QUARTER_TIME: u32 = 960;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteValue {
+    Whole,
+    Half,
+    Quarter,
+    Eighth,
+    Sixteenth,
+    ThirtySecond,
+    SixtyFourth,
+    OneHundredTwentyEighth,
+}
+
+impl NoteValue {
+    pub fn denominator(&self) -> u32 {
+        match *self {
+            NoteValue::Whole => 1,
+            NoteValue::Half => 2,
+            NoteValue::Quarter => 4,
+            NoteValue::Eighth => 8,
+            NoteValue::Sixteenth => 16,
+            NoteValue::ThirtySecond => 32,
+            NoteValue::SixtyFourth => 64,
+            NoteValue::OneHundredTwentyEighth => 128,
+        }
+    }
+
+    /// English name, e.g. "quarter" or "thirty-second".
+    pub fn to_note_name(&self) -> &'static str {
+        match *self {
+            NoteValue::Whole => "whole",
+            NoteValue::Half => "half",
+            NoteValue::Quarter => "quarter",
+            NoteValue::Eighth => "eighth",
+            NoteValue::Sixteenth => "sixteenth",
+            NoteValue::ThirtySecond => "thirty-second",
+            NoteValue::SixtyFourth => "sixty-fourth",
+            NoteValue::OneHundredTwentyEighth => "hundred-twenty-eighth",
+        }
+    }
+
+    /// Duration as written in LilyPond, e.g. "4" for a quarter note.
+    pub fn to_lilypond_symbol(&self) -> &'static str {
+        match *self {
+            NoteValue::Whole => "1",
+            NoteValue::Half => "2",
+            NoteValue::Quarter => "4",
+            NoteValue::Eighth => "8",
+            NoteValue::Sixteenth => "16",
+            NoteValue::ThirtySecond => "32",
+            NoteValue::SixtyFourth => "64",
+            NoteValue::OneHundredTwentyEighth => "128",
+        }
+    }
+
+    fn from_gpif(name: &str) -> Option<NoteValue> {
+        match name {
+            "Whole" => Some(NoteValue::Whole),
+            "Half" => Some(NoteValue::Half),
+            "Quarter" => Some(NoteValue::Quarter),
+            "Eighth" => Some(NoteValue::Eighth),
+            "16th" => Some(NoteValue::Sixteenth),
+            "32nd" => Some(NoteValue::ThirtySecond),
+            "64th" => Some(NoteValue::SixtyFourth),
+            "128th" => Some(NoteValue::OneHundredTwentyEighth),
+            _ => None,
+        }
+    }
+}
+
+/// `enters` notes played in the time of `times`, e.g. 3:2 for a triplet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tuplet {
+    pub enters: u32,
+    pub times: u32,
+}
+
+impl Tuplet {
+    /// Factor the tuplet scales note lengths by, 2/3 for a triplet.
+    /// A malformed tuplet with no entering notes leaves lengths alone, like `Rhythm::time`.
+    pub fn ratio(&self) -> f64 {
+        if self.enters == 0 {
+            return 1.0;
+        }
+        self.times as f64 / self.enters as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rhythm {
+    pub value: NoteValue,
+    pub dots: u8,
+    pub tuplet: Option<Tuplet>,
+}
+
+impl Default for Rhythm {
+    fn default() -> Rhythm {
+        Rhythm {
+            value: NoteValue::Quarter,
+            dots: 0,
+            tuplet: None,
+        }
+    }
+}
+
+impl Rhythm {
+    /// Length in ticks, see `QUARTER_TIME`.
+    pub fn time(&self) -> u32 {
+        let base = QUARTER_TIME * 4 / self.value.denominator();
+        let mut time = base;
+        let mut dot = base;
+        for _ in 0..self.dots {
+            dot /= 2;
+            time += dot;
+        }
+        match self.tuplet {
+            // Tuplet sizes come straight from the file, saturate rather than overflow.
+            Some(tuplet) if tuplet.enters > 0 => {
+                let scaled = time as u64 * tuplet.times as u64 / tuplet.enters as u64;
+                cmp::min(scaled, u32::MAX as u64) as u32
+            }
+            _ => time,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    /// Zero based string index, counted from the lowest string.
+    pub string: u8,
+    pub fret: u8,
+    /// Note is tied to the next one.
+    pub tie_origin: bool,
+    /// Note continues the previous one.
+    pub tie_destination: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Score {
+    pub title: String,
+    pub artist: String,
+    /// First bar is a pickup bar.
+    pub anacrusis: bool,
+    /// Tempo automations of the master track, ordered by bar and position.
+    pub tempo_changes: Vec<TempoChange>,
+    pub tracks: Vec<Track>,
+    pub master_bars: Vec<MasterBar>,
+}
+
+impl Score {
+    /// Tempo in effect at the start of `bar`.
+    /// Bars before the first automation (e.g. an anacrusis) use the initial tempo.
+    pub fn tempo_at(&self, bar: u32) -> Option<f32> {
+        self.tempo_changes
+            .iter()
+            .rev()
+            .find(|change| change.bar < bar || (change.bar == bar && change.position <= 0.0))
+            .or(self.tempo_changes.first())
+            .map(|change| change.bpm)
+    }
+
+    /// Every note of the score, track by track, then in bar, voice and beat order.
+    pub fn notes<'a>(
+        &'a self,
+    ) -> impl Iterator<Item = (&'a Track, &'a Bar, &'a Beat, &'a Note)> + 'a {
+        self.tracks
+            .iter()
+            .enumerate()
+            .flat_map(move |(index, track)| {
+                self.master_bars
+                    .iter()
+                    .filter_map(move |master_bar| master_bar.bars.get(index))
+                    .flat_map(|bar| {
+                        bar.voices
+                            .iter()
+                            .flat_map(|voice| voice.beats.iter())
+                            .map(move |beat| (bar, beat))
+                    })
+                    .flat_map(move |(bar, beat)| {
+                        beat.notes.iter().map(move |note| (track, bar, beat, note))
+                    })
+            })
+    }
+}
+
+pub fn parse(xml: &[u8]) -> io::Result<Score> {
+    let root = try!(parse_tree(xml));
+    let mut score = Score::default();
+    if let Some(info) = root.child("Score") {
+        score.title = info.child_text("Title").unwrap_or("").to_owned();
+        score.artist = info.child_text("Artist").unwrap_or("").to_owned();
+    }
+    if let Some(master_track) = root.child("MasterTrack") {
+        score.anacrusis = master_track.child("Anacrusis").is_some();
+        score.tempo_changes = try!(read_tempo_changes(master_track));
+    }
+    if let Some(tracks) = root.child("Tracks") {
+        score.tracks = try!(tracks.children("Track").map(read_track).collect());
+    }
+    score.master_bars = try!(read_master_bars(&root));
+    Ok(score)
+}
+
+/// Elements of a flat GPIF table (`<Bars>`, `<Beats>`, ...) keyed by their id.
+struct Table<'a> {
+    kind: &'static str,
+    elements: HashMap<&'a str, &'a Element>,
+}
+
+impl<'a> Table<'a> {
+    fn new(root: &'a Element, section: &str, kind: &'static str) -> Table<'a> {
+        let mut elements = HashMap::new();
+        if let Some(section) = root.child(section) {
+            for element in section.children(kind) {
+                if let Some(id) = element.attr("id") {
+                    elements.insert(id, element);
+                }
+            }
+        }
+        Table {
+            kind: kind,
+            elements: elements,
+        }
+    }
+
+    /// Resolves a whitespace separated id list, `-1` marks an empty slot.
+    fn resolve(&self, ids: Option<&str>, owner: &str) -> io::Result<Vec<&'a Element>> {
+        let mut elements = vec![];
+        for id in ids
+            .unwrap_or("")
+            .split_whitespace()
+            .filter(|&id| id != "-1")
+        {
+            match self.elements.get(id) {
+                Some(element) => elements.push(*element),
+                None => {
+                    return Err(format_error(format!(
+                        "{} references missing {} {}",
+                        owner, self.kind, id
+                    )))
+                }
+            }
+        }
+        Ok(elements)
+    }
+}
+
+/// Bars, beats and notes are referenced by id, so a small document can list the same ones
+/// over and over. Scores resolving to more than this many of them in total are rejected.
+const MAX_RESOLVED_ELEMENTS: usize = 1 << 20;
+
+/// Voices per bar in Guitar Pro.
+const MAX_VOICES: usize = 4;
+
+/// Counts resolved bars, beats and notes against `MAX_RESOLVED_ELEMENTS`.
+fn count_resolved(resolved: &mut usize, count: usize, owner: &str) -> io::Result<()> {
+    *resolved += count;
+    if *resolved > MAX_RESOLVED_ELEMENTS {
+        return Err(format_error(format!(
+            "{} takes the score past {} bars, beats and notes",
+            owner, MAX_RESOLVED_ELEMENTS
+        )));
+    }
+    Ok(())
+}
+
+fn read_master_bars(root: &Element) -> io::Result<Vec<MasterBar>> {
+    let bars = Table::new(root, "Bars", "Bar");
+    let voices = Table::new(root, "Voices", "Voice");
+    let beats = Table::new(root, "Beats", "Beat");
+    let notes = Table::new(root, "Notes", "Note");
+    let rhythms = Table::new(root, "Rhythms", "Rhythm");
+
+    let mut master_bars = vec![];
+    let mut resolved = 0;
+    let master_bar_elements = match root.child("MasterBars") {
+        Some(master_bars) => master_bars.children("MasterBar").collect(),
+        None => vec![],
+    };
+    for (index, master_bar) in master_bar_elements.into_iter().enumerate() {
+        let owner = format!("MasterBar {}", index);
+        let mut master_bar_bars = vec![];
+        let bar_elements = try!(bars.resolve(master_bar.child_text("Bars"), &owner));
+        try!(count_resolved(&mut resolved, bar_elements.len(), &owner));
+        for bar in bar_elements {
+            let owner = format!("Bar {}", bar.attr("id").unwrap_or("?"));
+            let mut bar_voices = vec![];
+            let voice_elements = try!(voices.resolve(bar.child_text("Voices"), &owner));
+            if voice_elements.len() > MAX_VOICES {
+                return Err(format_error(format!(
+                    "{} has {} voices, at most {} are allowed",
+                    owner,
+                    voice_elements.len(),
+                    MAX_VOICES
+                )));
+            }
+            for voice in voice_elements {
+                let owner = format!("Voice {}", voice.attr("id").unwrap_or("?"));
+                let mut voice_beats = vec![];
+                let beat_elements = try!(beats.resolve(voice.child_text("Beats"), &owner));
+                try!(count_resolved(&mut resolved, beat_elements.len(), &owner));
+                for beat in beat_elements {
+                    let owner = format!("Beat {}", beat.attr("id").unwrap_or("?"));
+                    let rhythm_ref = beat.child("Rhythm").and_then(|rhythm| rhythm.attr("ref"));
+                    let rhythm = match try!(rhythms.resolve(rhythm_ref, &owner)).first() {
+                        Some(rhythm) => try!(read_rhythm(rhythm)),
+                        None => Rhythm::default(),
+                    };
+                    let mut beat_notes = vec![];
+                    let note_elements = try!(notes.resolve(beat.child_text("Notes"), &owner));
+                    try!(count_resolved(&mut resolved, note_elements.len(), &owner));
+                    for note in note_elements {
+                        beat_notes.push(try!(read_note(note)));
+                    }
+                    voice_beats.push(Beat {
+                        rhythm: rhythm,
+                        notes: beat_notes,
+                    });
+                }
+                bar_voices.push(Voice { beats: voice_beats });
+            }
+            master_bar_bars.push(Bar { voices: bar_voices });
+        }
+        master_bars.push(MasterBar {
+            bars: master_bar_bars,
+        });
+    }
+    Ok(master_bars)
+}
+
+fn read_rhythm(rhythm: &Element) -> io::Result<Rhythm> {
+    let note_value = rhythm.child_text("NoteValue").unwrap_or("");
+    let value = match NoteValue::from_gpif(note_value) {
+        Some(value) => value,
+        None => {
+            return Err(format_error(format!(
+                "Unknown note value: {:?}",
+                note_value
+            )))
+        }
+    };
+    let dots = match rhythm.child("AugmentationDot") {
+        Some(dot) => try!(parse_value(
+            dot.attr("count").unwrap_or("1"),
+            "augmentation dot count"
+        )),
+        None => 0,
+    };
+    let tuplet = match rhythm.child("PrimaryTuplet") {
+        Some(tuplet) => Some(Tuplet {
+            enters: try!(parse_value(tuplet.attr("num").unwrap_or(""), "tuplet num")),
+            times: try!(parse_value(tuplet.attr("den").unwrap_or(""), "tuplet den")),
+        }),
+        None => None,
+    };
+    Ok(Rhythm {
+        value: value,
+        dots: dots,
+        tuplet: tuplet,
+    })
+}
+
+fn read_track(track: &Element) -> io::Result<Track> {
+    // GP6 keeps the tuning in the track properties, GP7 moved it into the staff.
+    let properties = track.child("Properties").into_iter().chain(
+        track
+            .child("Staves")
+            .into_iter()
+            .flat_map(|staves| staves.children("Staff"))
+            .filter_map(|staff| staff.child("Properties")),
+    );
+    let pitches = properties
+        .flat_map(|properties| properties.children("Property"))
+        .find(|property| property.attr("name") == Some("Tuning"))
+        .and_then(|property| property.child_text("Pitches"))
+        .unwrap_or("");
+    let mut tuning = vec![];
+    for pitch in pitches.split_whitespace() {
+        tuning.push(try!(parse_value(pitch, "tuning pitch")));
+    }
+    Ok(Track {
+        name: track.child_text("Name").unwrap_or("").to_owned(),
+        tuning: Tuning(tuning),
+    })
+}
+
+fn read_note(note: &Element) -> io::Result<Note> {
+    let mut string = 0;
+    let mut fret = 0;
+    if let Some(properties) = note.child("Properties") {
+        for property in properties.children("Property") {
+            match property.attr("name") {
+                Some("String") => {
+                    string = try!(parse_value(
+                        property.child_text("String").unwrap_or(""),
+                        "note string",
+                    ))
+                }
+                Some("Fret") => {
+                    fret = try!(parse_value(
+                        property.child_text("Fret").unwrap_or(""),
+                        "note fret",
+                    ))
+                }
+                _ => {}
+            }
+        }
+    }
+    let tie = note.child("Tie");
+    Ok(Note {
+        string: string,
+        fret: fret,
+        tie_origin: tie.and_then(|tie| tie.attr("origin")) == Some("true"),
+        tie_destination: tie.and_then(|tie| tie.attr("destination")) == Some("true"),
+    })
+}
+
+fn read_tempo_changes(master_track: &Element) -> io::Result<Vec<TempoChange>> {
+    let mut tempo_changes = vec![];
+    let automations = match master_track.child("Automations") {
+        Some(automations) => automations,
+        None => return Ok(tempo_changes),
+    };
+    for automation in automations.children("Automation") {
+        // Newer exports use a <Type> child, some older ones a type attribute.
+        let automation_type = automation
+            .attr("type")
+            .or_else(|| automation.child_text("Type"));
+        if automation_type != Some("Tempo") {
+            continue;
+        }
+        // Value is "<bpm> <reference note>", only the bpm is interesting.
+        let value = automation.child_text("Value").unwrap_or("");
+        let bpm = try!(parse_value(
+            value.split_whitespace().next().unwrap_or(""),
+            "tempo automation value",
+        ));
+        tempo_changes.push(TempoChange {
+            bar: try!(parse_value(
+                automation.child_text("Bar").unwrap_or("0"),
+                "automation bar"
+            )),
+            position: try!(parse_value(
+                automation.child_text("Position").unwrap_or("0"),
+                "automation position",
+            )),
+            bpm: bpm,
+            linear: automation.child_text("Linear") == Some("true"),
+        });
+    }
+    tempo_changes.sort_by(|a, b| {
+        a.bar.cmp(&b.bar).then(
+            a.position
+                .partial_cmp(&b.position)
+                .unwrap_or(Ordering::Equal),
+        )
+    });
+    Ok(tempo_changes)
+}
+
+fn parse_value<T: FromStr>(text: &str, what: &str) -> io::Result<T> {
+    text.parse()
+        .map_err(|_| format_error(format!("Invalid {}: {:?}", what, text)))
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|&&(ref key, _)| key == name)
+            .map(|&(_, ref value)| value.as_str())
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    fn child_text(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|child| child.text.trim())
+    }
+}
+
+/// Real scores nest about ten levels deep. Deeper documents are rejected, xml-rs gets
+/// quadratically slower with depth and the tree would be dropped recursively.
+const MAX_DEPTH: usize = 64;
+
+fn parse_tree(xml: &[u8]) -> io::Result<Element> {
+    let mut stack: Vec<Element> = vec![];
+    for event in EventReader::new(xml) {
+        match try!(event.map_err(|e| format_error(format!("Malformed gpif XML: {}", e)))) {
+            XmlEvent::StartElement { .. } if stack.len() >= MAX_DEPTH => {
+                return Err(format_error(format!(
+                    "gpif elements nested more than {} levels deep",
+                    MAX_DEPTH
+                )))
+            }
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => stack.push(Element {
+                name: name.local_name,
+                attributes: attributes
+                    .into_iter()
+                    .map(|attribute| (attribute.name.local_name, attribute.value))
+                    .collect(),
+                children: vec![],
+                text: String::new(),
+            }),
+            XmlEvent::EndElement { .. } => {
+                let element = match stack.pop() {
+                    Some(element) => element,
+                    None => break,
+                };
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Ok(element),
+                }
+            }
+            XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                if let Some(element) = stack.last_mut() {
+                    element.text.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(format_error("gpif document has no root element".to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    const MASTER_TRACK: &'static str = r#"<?xml version="1.0" encoding="utf-8"?>
+<GPIF>
+  <MasterTrack>
+    <Tracks>0</Tracks>
+    <Automations>
+      <Automation>
+        <Type>Tempo</Type>
+        <Linear>true</Linear>
+        <Bar>4</Bar>
+        <Position>0.5</Position>
+        <Value>90 2</Value>
+      </Automation>
+      <Automation>
+        <Type>Tempo</Type>
+        <Linear>false</Linear>
+        <Bar>0</Bar>
+        <Position>0</Position>
+        <Value>120 2</Value>
+      </Automation>
+      <Automation type="Tempo">
+        <Bar>2</Bar>
+        <Value>140</Value>
+      </Automation>
+      <Automation>
+        <Type>SyncPoint</Type>
+        <Bar>1</Bar>
+        <Value>7</Value>
+      </Automation>
+    </Automations>
+    <Anacrusis />
+  </MasterTrack>
+</GPIF>"#;
+
+    #[test]
+    pub fn test_parse_tempo_changes() {
+        let score = super::parse(MASTER_TRACK.as_bytes()).unwrap();
+        assert!(score.anacrusis);
+        let bars: Vec<_> = score.tempo_changes.iter().map(|c| c.bar).collect();
+        assert_eq!(bars, vec![0, 2, 4]);
+        assert_eq!(score.tempo_changes[0].bpm, 120.0);
+        assert!(!score.tempo_changes[0].linear);
+        assert_eq!(score.tempo_changes[2].position, 0.5);
+        assert!(score.tempo_changes[2].linear);
+    }
+
+    #[test]
+    pub fn test_tempo_at() {
+        let score = super::parse(MASTER_TRACK.as_bytes()).unwrap();
+        assert_eq!(score.tempo_at(0), Some(120.0));
+        assert_eq!(score.tempo_at(1), Some(120.0));
+        assert_eq!(score.tempo_at(2), Some(140.0));
+        assert_eq!(score.tempo_at(4), Some(140.0));
+        assert_eq!(score.tempo_at(5), Some(90.0));
+    }
+
+    #[test]
+    pub fn test_parse_bars() {
+        let score = super::parse(include_bytes!("../../test_data/two_tracks.gpif")).unwrap();
+        assert_eq!(score.title, "Two Tracks");
+        assert_eq!(score.artist, "rust-gpx-reader");
+        let names: Vec<_> = score.tracks.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["Lead", "Bass"]);
+        assert_eq!(score.tempo_at(1), Some(100.0));
+        assert_eq!(score.master_bars.len(), 2);
+
+        let lead = &score.master_bars[0].bars[0];
+        assert_eq!(lead.voices.len(), 1);
+        let beats = &lead.voices[0].beats;
+        assert_eq!(beats.len(), 2);
+        assert_eq!(beats[0].notes[0].string, 2);
+        assert_eq!(beats[0].notes[0].fret, 3);
+        assert!(beats[0].notes[0].tie_origin);
+        assert!(beats[1].is_rest());
+        assert_eq!(beats[0].rhythm.value, super::NoteValue::Quarter);
+        assert_eq!(beats[0].rhythm.time(), 960);
+
+        let tied = &score.master_bars[1].bars[0].voices[0].beats[0].notes[0];
+        assert!(tied.tie_destination);
+        assert!(!tied.tie_origin);
+        assert_eq!(tied.fret, 3);
+
+        let bass = &score.master_bars[0].bars[1].voices[0].beats[0];
+        let frets: Vec<_> = bass.notes.iter().map(|n| (n.string, n.fret)).collect();
+        assert_eq!(frets, vec![(0, 5), (1, 7)]);
+    }
+
+    #[test]
+    pub fn test_notes() {
+        let score = super::parse(include_bytes!("../../test_data/two_tracks.gpif")).unwrap();
+        let notes: Vec<_> = score
+            .notes()
+            .map(|(track, _, _, note)| (track.name.as_str(), note.fret))
+            .collect();
+        assert_eq!(
+            notes,
+            vec![
+                ("Lead", 3),
+                ("Lead", 3),
+                ("Bass", 5),
+                ("Bass", 7),
+                ("Bass", 0)
+            ]
+        );
+        assert_eq!(
+            score.notes().map(|(_, _, _, note)| note.fret).max(),
+            Some(7)
+        );
+        let (_, bar, beat, _) = score.notes().nth(3).unwrap();
+        assert_eq!(beat.notes.len(), 2);
+        assert!(::std::ptr::eq(bar, &score.master_bars[0].bars[1]));
+        assert_eq!(super::Score::default().notes().count(), 0);
+    }
+
+    #[test]
+    pub fn test_tuplet_ratio() {
+        use super::{NoteValue, Rhythm, Tuplet};
+        let tuplet = |enters, times| Tuplet {
+            enters: enters,
+            times: times,
+        };
+        assert_eq!(tuplet(1, 1).ratio(), 1.0);
+        assert_eq!(tuplet(3, 2).ratio(), 2.0 / 3.0);
+        assert_eq!(tuplet(0, 2).ratio(), 1.0);
+        let tuplets = [
+            (3, 2),
+            (5, 4),
+            (6, 4),
+            (7, 4),
+            (9, 8),
+            (10, 8),
+            (11, 8),
+            (12, 8),
+        ];
+        for &(enters, times) in &tuplets {
+            let ratio = tuplet(enters, times).ratio();
+            assert_eq!(ratio, times as f64 / enters as f64);
+            // Agrees with the integer tick arithmetic, which rounds down.
+            let rhythm = Rhythm {
+                value: NoteValue::Eighth,
+                dots: 0,
+                tuplet: Some(tuplet(enters, times)),
+            };
+            assert_eq!((480.0 * ratio) as u32, rhythm.time());
+        }
+    }
+
+    #[test]
+    pub fn test_tuning() {
+        use super::Tuning;
+        let score = super::parse(include_bytes!("../../test_data/two_tracks.gpif")).unwrap();
+        assert_eq!(score.tracks[0].tuning.0, vec![40, 45, 50, 55, 59, 64]);
+        assert_eq!(score.tracks[0].tuning.name(), Some("Standard"));
+        assert_eq!(score.tracks[1].tuning.0.len(), 4);
+        assert_eq!(score.tracks[1].tuning.name(), None);
+
+        let drop_d = r#"<GPIF><Tracks><Track id="0"><Name>Drop</Name><Staves><Staff><Properties>
+            <Property name="Tuning"><Pitches>38 45 50 55 59 64</Pitches></Property>
+            </Properties></Staff></Staves></Track></Tracks></GPIF>"#;
+        let score = super::parse(drop_d.as_bytes()).unwrap();
+        assert_eq!(score.tracks[0].tuning.name(), Some("Drop D"));
+
+        assert_eq!(Tuning(vec![38, 45, 50, 55, 57, 62]).name(), Some("DADGAD"));
+        assert_eq!(Tuning(vec![38, 43, 50, 55, 59, 62]).name(), Some("Open G"));
+        assert_eq!(Tuning(vec![39, 44, 49, 54, 58, 63]).name(), Some("Half-step down"));
+        assert_eq!(Tuning(vec![]).name(), None);
+
+        let bad = r#"<GPIF><Tracks><Track><Properties><Property name="Tuning">
+            <Pitches>40 low-e</Pitches></Property></Properties></Track></Tracks></GPIF>"#;
+        assert!(super::parse(bad.as_bytes()).is_err());
+    }
+
+    #[test]
+    pub fn test_note_value_names() {
+        use super::NoteValue::*;
+        let values = [
+            (Whole, "whole", "1"),
+            (Half, "half", "2"),
+            (Quarter, "quarter", "4"),
+            (Eighth, "eighth", "8"),
+            (Sixteenth, "sixteenth", "16"),
+            (ThirtySecond, "thirty-second", "32"),
+            (SixtyFourth, "sixty-fourth", "64"),
+            (OneHundredTwentyEighth, "hundred-twenty-eighth", "128"),
+        ];
+        for &(value, name, lilypond) in &values {
+            assert_eq!(value.to_note_name(), name);
+            assert_eq!(value.to_lilypond_symbol(), lilypond);
+            assert_eq!(lilypond.parse::<u32>().unwrap(), value.denominator());
+        }
+    }
+
+    #[test]
+    pub fn test_parse_rhythms() {
+        use super::{NoteValue, Tuplet};
+        let xml = r#"<GPIF>
+            <MasterBars><MasterBar><Bars>0</Bars></MasterBar></MasterBars>
+            <Bars><Bar id="0"><Voices>0 -1 -1 -1</Voices></Bar></Bars>
+            <Voices><Voice id="0"><Beats>0 1 0 2 3</Beats></Voice></Voices>
+            <Beats>
+              <Beat id="0"><Rhythm ref="0"/></Beat>
+              <Beat id="1"><Rhythm ref="1"/></Beat>
+              <Beat id="2"></Beat>
+              <Beat id="3"><Rhythm ref="2"/></Beat>
+            </Beats>
+            <Rhythms>
+              <Rhythm id="0">
+                <NoteValue>Eighth</NoteValue>
+                <PrimaryTuplet num="3" den="2"/>
+              </Rhythm>
+              <Rhythm id="1">
+                <NoteValue>Eighth</NoteValue>
+                <AugmentationDot count="2"/>
+              </Rhythm>
+              <Rhythm id="2">
+                <NoteValue>Eighth</NoteValue>
+                <PrimaryTuplet num="3" den="100000000"/>
+              </Rhythm>
+            </Rhythms>
+        </GPIF>"#;
+        let score = super::parse(xml.as_bytes()).unwrap();
+        let beats = &score.master_bars[0].bars[0].voices[0].beats;
+
+        let triplet = beats[0].rhythm;
+        assert_eq!(triplet.value, NoteValue::Eighth);
+        assert_eq!(
+            triplet.tuplet,
+            Some(Tuplet {
+                enters: 3,
+                times: 2
+            })
+        );
+        assert_eq!(triplet.time(), 320);
+
+        let double_dotted = beats[1].rhythm;
+        assert_eq!(double_dotted.dots, 2);
+        assert_eq!(double_dotted.tuplet, None);
+        assert_eq!(double_dotted.time(), 480 + 240 + 120);
+
+        assert_eq!(beats[2], beats[0]);
+        assert_eq!(beats[3].rhythm.value, NoteValue::Quarter);
+        assert_eq!(beats[4].rhythm.time(), u32::MAX);
+    }
+
+    #[test]
+    pub fn test_dangling_note_id() {
+        let xml = r#"<GPIF>
+            <MasterBars><MasterBar><Bars>0</Bars></MasterBar></MasterBars>
+            <Bars><Bar id="0"><Voices>0 -1 -1 -1</Voices></Bar></Bars>
+            <Voices><Voice id="0"><Beats>0</Beats></Voice></Voices>
+            <Beats><Beat id="0"><Notes>17</Notes></Beat></Beats>
+            <Notes></Notes>
+        </GPIF>"#;
+        let error = super::parse(xml.as_bytes()).unwrap_err();
+        assert_eq!(error.to_string(), "Beat 0 references missing Note 17");
+    }
+
+    #[test]
+    pub fn test_no_master_track() {
+        let score = super::parse(b"<GPIF></GPIF>").unwrap();
+        assert!(!score.anacrusis);
+        assert_eq!(score.tempo_at(0), None);
+        assert!(super::parse(b"<GPIF>").is_err());
+    }
+
+    #[test]
+    pub fn test_resolved_element_limit() {
+        // 12000 references to beat 0, which references note 0 12000 times: 144M notes.
+        let ids = "0 ".repeat(12000);
+        let xml = format!(
+            r#"<GPIF>
+            <MasterBars><MasterBar><Bars>0</Bars></MasterBar></MasterBars>
+            <Bars><Bar id="0"><Voices>0</Voices></Bar></Bars>
+            <Voices><Voice id="0"><Beats>{}</Beats></Voice></Voices>
+            <Beats><Beat id="0"><Notes>{}</Notes></Beat></Beats>
+            <Notes><Note id="0"/></Notes>
+        </GPIF>"#,
+            ids, ids
+        );
+        let err = super::parse(xml.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("beats and notes"));
+
+        let bars = format!(
+            r#"<GPIF><MasterBars>{}</MasterBars><Bars><Bar id="0"/></Bars></GPIF>"#,
+            format!("<MasterBar><Bars>{}</Bars></MasterBar>", ids).repeat(100)
+        );
+        let err = super::parse(bars.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("MasterBar 87"));
+
+        let voices = r#"<GPIF>
+            <MasterBars><MasterBar><Bars>0</Bars></MasterBar></MasterBars>
+            <Bars><Bar id="0"><Voices>0 0 0 0 0</Voices></Bar></Bars>
+            <Voices><Voice id="0"></Voice></Voices>
+        </GPIF>"#;
+        let err = super::parse(voices.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("5 voices"));
+    }
+
+    #[test]
+    pub fn test_nesting_limit() {
+        use gpx::{self, Archive};
+        let nested = |depth| format!("{}{}", "<a>".repeat(depth), "</a>".repeat(depth));
+        assert!(super::parse(nested(super::MAX_DEPTH).as_bytes()).is_ok());
+        let err = super::parse(nested(super::MAX_DEPTH + 1).as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("nested"));
+        // Bails out at the limit, long before the rest of a huge document is parsed.
+        assert!(super::parse(nested(80000).as_bytes()).is_err());
+
+        // The fuzz corpus entry: a BCFS archive whose score is 1000 levels deep.
+        let files = gpx::read(include_bytes!("../../test_data/deep_nesting.gpx").to_vec()).unwrap();
+        let err = super::parse(files.score().unwrap().data()).unwrap_err();
+        assert!(err.to_string().contains("nested"));
+    }
+}
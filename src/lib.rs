@@ -1,9 +1,42 @@
 
 extern crate byteorder;
-extern crate env_logger;
+#[cfg(feature = "logging")]
 #[macro_use]
 extern crate log;
+#[cfg(feature = "rustc-serialize")]
 extern crate rustc_serialize;
+#[cfg(feature = "gpif")]
+extern crate xml;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+#[cfg(feature = "zip")]
+extern crate zip;
+#[cfg(test)]
+extern crate proptest;
 
+// Without the logging feature the log macros only type check their arguments.
+#[cfg(not(feature = "logging"))]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format!($($arg)*);
+        }
+    };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format!($($arg)*);
+        }
+    };
+}
+
+pub mod bitbuffer;
 pub mod gpx;
-mod bitbuffer;
+mod detect;
+
+pub use detect::{detect, Detected};
+#[cfg(feature = "gpif")]
+pub use detect::read_any;
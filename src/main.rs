@@ -1,29 +1,62 @@
-
 extern crate env_logger;
 extern crate guitar_tabs;
+#[cfg(feature = "memmap")]
+extern crate memmap;
 
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
 use std::path::Path;
 use guitar_tabs::gpx;
 
+/// Usage: `gpx_reader [--mmap] [FILE]`, reads stdin without a file.
+/// `--mmap` maps the file instead of reading it into memory, it needs the `memmap` feature.
 fn main() {
     env_logger::init().unwrap();
 
-    let args: Vec<_> = std::env::args().collect();
-    let mut file_data = vec![];
-    if args.len() > 1 {
-        File::open(&Path::new(&args[1]))
-            .unwrap()
-            .read_to_end(&mut file_data)
-            .unwrap();
-    } else {
-        let mut stdin = std::io::stdin();
-        stdin.read_to_end(&mut file_data).unwrap();
+    let mut args: Vec<_> = std::env::args().skip(1).collect();
+    let mmap = match args.iter().position(|arg| arg == "--mmap") {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    };
+    let result = match args.first() {
+        Some(path) if mmap => read_mapped(Path::new(path)),
+        Some(path) => {
+            let mut file_data = vec![];
+            File::open(&Path::new(path))
+                .unwrap()
+                .read_to_end(&mut file_data)
+                .unwrap();
+            gpx::read(file_data)
+        }
+        None => {
+            let mut file_data = vec![];
+            let mut stdin = std::io::stdin();
+            stdin.read_to_end(&mut file_data).unwrap();
+            gpx::read(file_data)
+        }
     };
-    let files = match gpx::read(file_data) {
+    let files = match result {
         Ok(files) => files,
         Err(error) => panic!(error),
     };
     println!("{:?}", files);
 }
+
+#[cfg(feature = "memmap")]
+fn read_mapped(path: &Path) -> io::Result<Vec<gpx::File>> {
+    let file = try!(File::open(path));
+    // Undefined behaviour if another process truncates the file while it's mapped.
+    let map = try!(unsafe { memmap::Mmap::map(&file) });
+    gpx::read_slice(&map)
+}
+
+#[cfg(not(feature = "memmap"))]
+fn read_mapped(_path: &Path) -> io::Result<Vec<gpx::File>> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "--mmap requires the memmap feature",
+    ))
+}